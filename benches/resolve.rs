@@ -0,0 +1,137 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Times resolving synthetic documents that stress the try/catch resolver,
+//! and reports how many documents the store held before and after, so that
+//! regressions in time or in the alternatives explored show up.
+//!
+//! Run with `cargo bench --bench resolve`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use spadefmt::{
+    document::{Document, DocumentIdx, InternedDocumentStore},
+    resolve_try_catch::{PrintingContext, resolve_try_catch},
+};
+
+const MAX_WIDTH: usize = 100;
+const ITERATIONS: u32 = 20;
+
+/// `f(arg0, ..., argN)`, flat or with one argument per line, where each
+/// argument is itself a small call that may break.
+fn wide_argument_list(store: &mut InternedDocumentStore) -> DocumentIdx {
+    const ARGUMENTS: usize = 2_000;
+    let newline = store.add(Document::Newline);
+    let comma = store.add(Document::Text(", ".into()));
+    let comma_newline = store.add(Document::Text(",".into()));
+
+    let arguments = (0..ARGUMENTS)
+        .map(|index| {
+            let flat =
+                store.add(Document::Text(format!("g({index}, {index})")));
+            let broken = store
+                .add(Document::Verbatim(format!("g(\n{index},\n{index})")));
+            store.add(Document::TryCatch(flat, broken))
+        })
+        .collect::<Vec<_>>();
+
+    let mut flat = vec![store.add(Document::Text("f(".into()))];
+    let mut broken = flat.clone();
+    for (index, argument) in arguments.iter().enumerate() {
+        if index > 0 {
+            flat.push(comma);
+            broken.push(comma_newline);
+        }
+        flat.push(*argument);
+        broken.extend([newline, *argument]);
+    }
+    let close = store.add(Document::Text(")".into()));
+    flat.push(close);
+    broken.extend([newline, close]);
+
+    let flat = store.add(Document::List(flat));
+    let broken = store.add(Document::List(broken));
+    let broken = store.add(Document::Nest(broken, 4));
+    store.add(Document::TryCatch(flat, broken))
+}
+
+/// `(...(x)...)`, where each level may break its contents onto an indented
+/// line.
+fn deep_nesting(store: &mut InternedDocumentStore) -> DocumentIdx {
+    const DEPTH: usize = 5_000;
+    let open = store.add(Document::Text("(".into()));
+    let close = store.add(Document::Text(")".into()));
+    let newline = store.add(Document::Newline);
+
+    let mut idx = store.add(Document::Text("x".into()));
+    for _ in 0..DEPTH {
+        let flat = store.add(Document::List(vec![open, idx, close]));
+        let broken_body = store.add(Document::List(vec![newline, idx]));
+        let broken_nest = store.add(Document::Nest(broken_body, 1));
+        let broken =
+            store.add(Document::List(vec![open, broken_nest, newline, close]));
+        idx = store.add(Document::TryCatch(flat, broken));
+    }
+    idx
+}
+
+/// Many sibling choices on separate lines, each between a line that fits and
+/// one that does not.
+fn many_unions(store: &mut InternedDocumentStore) -> DocumentIdx {
+    const UNIONS: usize = 20_000;
+    let newline = store.add(Document::Newline);
+    let mut lines = vec![];
+    for index in 0..UNIONS {
+        let too_wide =
+            store.add(Document::Text(format!("{index:>width$}", width = 120)));
+        let fits = store.add(Document::Text(index.to_string()));
+        lines.extend([store.add(Document::TryCatch(too_wide, fits)), newline]);
+    }
+    store.add(Document::List(lines))
+}
+
+fn bench(name: &str, build: fn(&mut InternedDocumentStore) -> DocumentIdx) {
+    let mut total = Duration::ZERO;
+    let mut sizes = (0, 0);
+    for _ in 0..ITERATIONS {
+        let mut store = InternedDocumentStore::default();
+        let idx = build(&mut store);
+        let before = store.len();
+
+        let start = Instant::now();
+        let resolved_idx = resolve_try_catch(
+            &mut store,
+            black_box(idx),
+            &mut PrintingContext::new(MAX_WIDTH),
+        );
+        total += start.elapsed();
+        black_box(resolved_idx);
+        sizes = (before, store.len());
+    }
+
+    println!(
+        "{name:<20} {:>10.3} ms/iter  {:>8} -> {:>8} documents",
+        (total / ITERATIONS).as_secs_f64() * 1000.0,
+        sizes.0,
+        sizes.1
+    );
+}
+
+fn main() {
+    bench("wide argument list", wide_argument_list);
+    bench("deep nesting", deep_nesting);
+    bench("many unions", many_unions);
+}
@@ -0,0 +1,51 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{env, fs};
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    // the spade crates are all pinned to the same git revision
+    let manifest =
+        fs::read_to_string("Cargo.toml").expect("failed to read Cargo.toml");
+    let spade_revision = manifest
+        .lines()
+        .find(|line| line.starts_with("spade-parser"))
+        .and_then(|line| line.split("rev = \"").nth(1))
+        .and_then(|rest| rest.split('"').next())
+        .unwrap_or("unknown");
+    println!("cargo:rustc-env=SPADEFMT_SPADE_REVISION={spade_revision}");
+
+    // the lock file records the version of the parser at that revision
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let spade_parser_version = lock
+        .split("[[package]]")
+        .find(|package| package.contains("name = \"spade-parser\""))
+        .and_then(|package| package.split("version = \"").nth(1))
+        .and_then(|rest| rest.split('"').next())
+        .unwrap_or("unknown");
+    println!(
+        "cargo:rustc-env=SPADEFMT_SPADE_PARSER_VERSION={spade_parser_version}"
+    );
+
+    let mut features = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect::<Vec<_>>();
+    features.sort();
+    println!("cargo:rustc-env=SPADEFMT_FEATURES={}", features.join(","));
+}
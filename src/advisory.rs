@@ -0,0 +1,148 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Advice on keeping files and items small enough to review, based on their
+//! formatted size.
+
+use std::io::{self, Write};
+
+use spade_ast as ast;
+use spade_codespan_reporting::{
+    diagnostic::{Diagnostic, Label},
+    files::SimpleFile,
+    term::{self, termcolor::Buffer},
+};
+
+use crate::{
+    FormatError, builder_for, config::Config, document_builder::span_of_item,
+    format_source, parse, resolve_and_print,
+};
+
+/// A short description of `item` for use in messages, e.g., "entity `main`".
+fn describe_item(item: &ast::Item) -> String {
+    match item {
+        ast::Item::Unit(unit) => {
+            let kind = match &*unit.head.unit_kind {
+                ast::UnitKind::Function => "fn",
+                ast::UnitKind::Entity => "entity",
+                ast::UnitKind::Pipeline(_) => "pipeline",
+            };
+            format!("{kind} `{}`", unit.head.name)
+        }
+        ast::Item::TraitDef(trait_definition) => {
+            format!("trait `{}`", trait_definition.name)
+        }
+        ast::Item::Type(type_declaration) => {
+            format!("type `{}`", type_declaration.name)
+        }
+        ast::Item::ExternalMod(external_module) => {
+            format!("extern mod `{}`", external_module.name)
+        }
+        ast::Item::Module(module) => format!("mod `{}`", module.name),
+        ast::Item::Use(_) => "use statement".into(),
+        ast::Item::ImplBlock(_) => "impl block".into(),
+    }
+}
+
+/// Flags `source` if its formatted form is longer than `max_file_lines`, and
+/// each top-level item whose formatted form is longer than `max_item_lines`.
+///
+/// The returned notes label spans of `source`.
+pub fn advise(
+    source: &str,
+    config: &Config,
+) -> Result<Vec<Diagnostic<()>>, FormatError> {
+    let root = parse(source)?;
+    let file = SimpleFile::new(String::new(), source.to_string());
+    let (mut document_store, item_idxs) =
+        builder_for(source, config).build_root_items(&root, &file);
+
+    let mut notes = vec![];
+    let file_lines = format_source(source, config)?.lines().count();
+    if file_lines > config.max_file_lines.inner {
+        notes.push(
+            Diagnostic::note()
+                .with_message(format!(
+                    "file is {file_lines} lines long when formatted, more \
+                     than the configured maximum of {}",
+                    config.max_file_lines.inner
+                ))
+                .with_notes(vec![
+                    "consider splitting it into several modules".into(),
+                ]),
+        );
+    }
+
+    let mut pending_item_idxs = item_idxs;
+    for item in &root.members {
+        let item_lines = resolve_and_print(
+            &mut document_store,
+            pending_item_idxs[0],
+            config,
+        )?
+        .lines()
+        .count();
+        // keep memory bounded by dropping this item's documents, including
+        // every alternative explored while resolving it
+        pending_item_idxs = document_store.compact(&pending_item_idxs[1..]);
+
+        if item_lines > config.max_item_lines.inner {
+            let span = span_of_item(item);
+            notes.push(
+                Diagnostic::note()
+                    .with_message(format!(
+                        "{} is {item_lines} lines long when formatted",
+                        describe_item(item)
+                    ))
+                    .with_labels(vec![
+                        Label::primary(
+                            (),
+                            span.start().to_usize()..span.end().to_usize(),
+                        )
+                        .with_message(format!(
+                            "more than the configured maximum of {}",
+                            config.max_item_lines.inner
+                        )),
+                    ])
+                    .with_notes(vec![
+                        "consider splitting it into smaller items".into(),
+                    ]),
+            );
+        }
+    }
+
+    Ok(notes)
+}
+
+/// Renders `notes`, such as those produced by [`advise`], for the file at
+/// `path` with contents `source` to `output`.
+pub fn emit(
+    path: &str,
+    source: &str,
+    notes: &[Diagnostic<()>],
+    output: &mut impl Write,
+    color: bool,
+) -> io::Result<()> {
+    let mut buffer = if color {
+        Buffer::ansi()
+    } else {
+        Buffer::no_color()
+    };
+    let file = SimpleFile::new(path, source);
+    let config = term::Config::default();
+    for note in notes {
+        term::emit(&mut buffer, &config, &file, note)
+            .map_err(io::Error::other)?;
+    }
+    output.write_all(buffer.as_slice())
+}
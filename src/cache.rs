@@ -0,0 +1,126 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Remembering which inputs are already formatted, so that repeated runs over
+//! an unchanged codebase can skip them.
+
+use std::{
+    fs::{self, File},
+    io,
+    time::SystemTime,
+};
+
+use camino::Utf8PathBuf;
+use snafu::ResultExt;
+
+use crate::{
+    config::Config,
+    content_hash,
+    error::{FormatError, IoSnafu},
+    version::VERSION_INFO,
+};
+
+/// The most markers kept in a cache directory. The least recently used ones
+/// beyond this are evicted.
+pub const MAX_ENTRIES: usize = 10_000;
+
+/// A directory of markers, one empty file per input known to be formatted
+/// already. Each marker is named after a hash of the input, the
+/// configuration, and the version of `spadefmt`, so changing any of them
+/// misses the cache.
+pub struct FormatCache {
+    dir: Utf8PathBuf,
+}
+
+impl FormatCache {
+    pub fn new(dir: impl Into<Utf8PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Whether `source` was marked as formatted according to `config`. A hit
+    /// counts as a use of the marker for eviction.
+    pub fn is_formatted(&self, source: &str, config: &Config) -> bool {
+        let path = self.marker_path(source, config);
+        // the marker may be evicted concurrently, which is just a miss
+        File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|marker| marker.set_modified(SystemTime::now()))
+            .is_ok()
+    }
+
+    /// Records that `source` is formatted according to `config`.
+    pub fn mark_formatted(
+        &self,
+        source: &str,
+        config: &Config,
+    ) -> Result<(), FormatError> {
+        fs::create_dir_all(&self.dir).context(IoSnafu { path: &self.dir })?;
+        let path = self.marker_path(source, config);
+        File::create(&path).context(IoSnafu { path })?;
+        Ok(())
+    }
+
+    /// Removes the least recently used markers until at most `max_entries`
+    /// remain.
+    pub fn evict(&self, max_entries: usize) -> Result<(), FormatError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return Ok(());
+            }
+            Err(error) => {
+                return Err(error).context(IoSnafu { path: &self.dir });
+            }
+        };
+
+        let mut markers = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let path = Utf8PathBuf::from_path_buf(entry.path()).ok()?;
+                if !path.file_name().is_some_and(is_marker_name) {
+                    return None;
+                }
+                let used = entry.metadata().ok()?.modified().ok()?;
+                Some((used, path))
+            })
+            .collect::<Vec<_>>();
+        if markers.len() <= max_entries {
+            return Ok(());
+        }
+        markers.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+        for (_, path) in markers.drain(max_entries..) {
+            match fs::remove_file(&path) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                    return Err(error).context(IoSnafu { path });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn marker_path(&self, source: &str, config: &Config) -> Utf8PathBuf {
+        let key = format!(
+            "{}\0{}\0{config:?}\0{source}",
+            VERSION_INFO.version, VERSION_INFO.spade_revision
+        );
+        self.dir.join(format!("{:016x}", content_hash(&key)))
+    }
+}
+
+/// Whether `name` could be the name of a marker, so that eviction leaves
+/// anything else in the directory alone.
+fn is_marker_name(name: &str) -> bool {
+    name.len() == 16 && name.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
@@ -11,10 +11,12 @@
 // copy of the GNU General Public License along with spadefmt. If not, see
 // <https://www.gnu.org/licenses/>.
 
-use std::env;
+use std::{env, process, str::FromStr};
 
 use argh::FromArgs;
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::explain::SourcePosition;
 
 /// Format Spade code
 #[derive(Default, FromArgs)]
@@ -27,25 +29,321 @@ pub struct Opts {
     #[argh(switch)]
     pub debug: bool,
 
+    /// instead of formatting, explain which layout was chosen for the
+    /// construct at line:column and why
+    #[argh(option)]
+    pub explain: Option<SourcePosition>,
+
+    /// after formatting the files in parallel, format them again one at a
+    /// time and fail if any output differs
+    #[argh(switch)]
+    pub audit_determinism: bool,
+
+    /// instead of formatting, print the smallest max_width at which each
+    /// file formats with no line wider than it
+    #[argh(switch)]
+    pub fit: bool,
+
+    /// check that every top-level item starts at column zero and leaves the
+    /// indentation unchanged, reporting the first one that does not
+    #[argh(switch)]
+    pub paranoid: bool,
+
+    /// check that the formatted code parses to the same syntax tree as the
+    /// input
+    #[argh(switch)]
+    pub verify: bool,
+
+    /// only fix indentation, spacing, and overlong lines, keeping existing
+    /// line breaks
+    #[argh(switch)]
+    pub minimal: bool,
+
+    /// use this configuration file instead of searching for the nearest
+    /// spadefmt.toml
+    #[argh(option)]
+    pub config_path: Option<Utf8PathBuf>,
+
+    /// format the top-level items that parse even if others do not, leaving
+    /// those as written with a warning
+    #[argh(switch)]
+    pub recover: bool,
+
+    /// only format the top-level items touching lines that differ from this
+    /// git revision, leaving the rest of each file byte-for-byte unchanged
+    #[argh(option)]
+    pub diff_base: Option<String>,
+
+    /// remember which files are already formatted in this directory, skipping
+    /// them on later runs until they, the configuration, or spadefmt change;
+    /// unused with --recover, --verify, or any mode other than formatting
+    #[argh(option)]
+    pub cache_dir: Option<Utf8PathBuf>,
+
+    /// ignore --cache-dir
+    #[argh(switch)]
+    pub no_cache: bool,
+
+    /// also report files and top-level items longer than max_file_lines and
+    /// max_item_lines once formatted
+    #[argh(switch)]
+    pub advise: bool,
+
+    /// when emitting to stdout, syntax highlight the formatted code with the
+    /// theme configured in spadefmt.toml, or the default one
+    #[argh(switch)]
+    pub color_output: bool,
+
+    /// instead of the formatted code, print the path of each file whose
+    /// formatted code differs from its contents, one per line, and fail if
+    /// there are any; a file stops being formatted at its first differing
+    /// item unless another mode or check is given. With `--message-format
+    /// json`, each such file is instead a JSON record on stdout pointing at
+    /// what would be reformatted
+    #[argh(switch, short = 'l')]
+    pub list_different: bool,
+
+    /// after formatting, print to stderr how many files were checked, how
+    /// many would be reformatted, and how many failed to parse
+    #[argh(switch)]
+    pub summary: bool,
+
+    /// what to emit for the formatted code: stdout (default), with each file
+    /// preceded by a `==> path <==` line when several are given, or hash
+    #[argh(option, default = "Emit::Stdout")]
+    pub emit: Emit,
+
+    /// how to report errors: human (default) or json, which writes one JSON
+    /// record per line to stderr
+    #[argh(option, default = "MessageFormat::Human")]
+    pub message_format: MessageFormat,
+
+    /// after formatting, print to stderr how many lines changed, how many
+    /// groups had to break, and the widest line, for each file
+    #[argh(switch)]
+    pub report: bool,
+
+    /// with --report, how to print it: human (default) for a table, or json
+    /// for one JSON record per file
+    #[argh(option, default = "MessageFormat::Human")]
+    pub report_format: MessageFormat,
+
+    /// log formatter internals to stderr at this level: error, warn, info,
+    /// debug, or trace
+    #[argh(option)]
+    pub log_level: Option<tracing::Level>,
+
+    /// with --log-level, log as JSON lines
+    #[argh(switch)]
+    pub log_json: bool,
+
     /// show version information
     #[argh(switch, short = 'v')]
     pub version: bool,
 
+    /// with --version, also show build and default configuration information
+    #[argh(switch)]
+    pub verbose: bool,
+
+    /// print a configuration file setting every option and exit: default
+    /// for the defaults, or current for the configuration that applies in
+    /// the working directory
+    #[argh(option)]
+    pub print_config: Option<PrintConfig>,
+
+    /// run a language server providing formatting over stdin and stdout
+    #[argh(switch)]
+    pub lsp: bool,
+
+    // files to format
+    #[argh(positional)]
+    pub files: Vec<Utf8PathBuf>,
+}
+
+/// Interactively explore how max_width changes the formatting of a file
+#[derive(FromArgs)]
+pub struct PlayOpts {
     // file to format
     #[argh(positional)]
     pub file: Utf8PathBuf,
 }
 
+impl PlayOpts {
+    /// Parses the command line as `spadefmt play ...`, or returns `None` if it
+    /// is not a `play` invocation (see [`subcommand_from_env`]). Exits on
+    /// `--help` or invalid arguments.
+    pub fn from_env() -> Option<Self> {
+        subcommand_from_env("play")
+    }
+}
+
+/// Format every Spade file of a Swim project in place
+#[derive(FromArgs)]
+pub struct SwimOpts {
+    /// the swim.toml of the project, instead of the nearest one to the
+    /// working directory
+    #[argh(option)]
+    pub manifest_path: Option<Utf8PathBuf>,
+
+    /// use this configuration file instead of searching for the nearest
+    /// spadefmt.toml to each file
+    #[argh(option)]
+    pub config_path: Option<Utf8PathBuf>,
+}
+
+impl SwimOpts {
+    /// Parses the command line as `spadefmt swim ...`, or returns `None` if it
+    /// is not a `swim` invocation (see [`subcommand_from_env`]). Exits on
+    /// `--help` or invalid arguments.
+    pub fn from_env() -> Option<Self> {
+        subcommand_from_env("swim")
+    }
+}
+
+/// Parses the command line as `spadefmt <name> ...`, or returns `None` if the
+/// first argument is not `name`. Exits on `--help` or invalid arguments.
+///
+/// A first argument naming an existing file is a file to format rather than
+/// the subcommand, so `spadefmt swim` still formats a file called `swim` in
+/// the working directory.
+fn subcommand_from_env<T: FromArgs>(name: &str) -> Option<T> {
+    let args = env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) != Some(name)
+        || Utf8Path::new(name).exists()
+    {
+        return None;
+    }
+    let command = format!("{} {name}", args[0]);
+    let rest = args[2..].iter().map(String::as_str).collect::<Vec<_>>();
+    match T::from_args(&[&command], &rest) {
+        Ok(opts) => Some(opts),
+        Err(early_exit) => {
+            if early_exit.status.is_ok() {
+                println!("{}", early_exit.output);
+                process::exit(0)
+            } else {
+                eprintln!("{}", early_exit.output);
+                process::exit(1)
+            }
+        }
+    }
+}
+
+/// What a run reports through its exit code, from least to most severe. A
+/// run with several outcomes exits with the most severe one.
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum ExitStatus {
+    /// Every file was formatted, or with `--list-different`, every file is
+    /// formatted already.
+    #[default]
+    Success,
+    /// With `--list-different`, some file is not formatted.
+    Different,
+    /// Some file does not parse.
+    ParseError,
+    /// Anything else went wrong.
+    InternalError,
+}
+
+impl ExitStatus {
+    /// The exit code of the process: 0, 1, 2, or 3, in order of severity.
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Success => 0,
+            Self::Different => 1,
+            Self::ParseError => 2,
+            Self::InternalError => 3,
+        }
+    }
+}
+
+/// What to produce from the formatted code.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Emit {
+    /// Print the formatted code. When there are several files, each one is
+    /// preceded by a `==> path <==` line and separated from the one before it
+    /// by a blank line, like `head` does.
+    #[default]
+    Stdout,
+    /// Print a stable hash of the formatted code without the code itself.
+    /// See [`crate::content_hash`].
+    Hash,
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdout" => Ok(Self::Stdout),
+            "hash" => Ok(Self::Hash),
+            _ => {
+                Err(format!("unknown emit kind `{s}`: expected stdout or hash"))
+            }
+        }
+    }
+}
+
+/// Which configuration to print.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PrintConfig {
+    /// The default configuration.
+    Default,
+    /// The configuration discovered from the working directory.
+    Current,
+}
+
+impl FromStr for PrintConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "current" => Ok(Self::Current),
+            _ => Err(format!(
+                "unknown config `{s}`: expected default or current"
+            )),
+        }
+    }
+}
+
+/// How errors and reports are written.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageFormat {
+    /// Rendered diagnostics with source snippets.
+    #[default]
+    Human,
+    /// Line-delimited JSON records with file, span, and message fields. See
+    /// [`crate::report::json_record`].
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown message format `{s}`: expected human or json"
+            )),
+        }
+    }
+}
+
 impl Opts {
     pub fn from_env() -> Self {
-        if env::args().len() == 2
-            && matches!(
-                env::args().nth(1).as_deref(),
-                Some("-v") | Some("--version")
-            )
+        let args = env::args().skip(1).collect::<Vec<_>>();
+        let is_version_flag = |arg: &String| {
+            matches!(arg.as_str(), "-v" | "--version" | "--verbose")
+        };
+        if args.iter().all(is_version_flag)
+            && args.iter().any(|arg| arg != "--verbose")
         {
             Opts {
                 version: true,
+                verbose: args.iter().any(|arg| arg == "--verbose"),
                 ..Default::default()
             }
         } else {
@@ -11,12 +11,32 @@
 // copy of the GNU General Public License along with spadefmt. If not, see
 // <https://www.gnu.org/licenses/>.
 
-use std::fmt::{self, Debug};
+use std::{
+    fmt::{self, Debug},
+    fs,
+};
 
+use camino::Utf8Path;
 use derivative::Derivative;
-use serde::Deserialize;
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{self, Visitor},
+    forward_to_deserialize_any,
+};
+use snafu::{ResultExt, ensure};
 use string16::{String16, string16};
 
+use crate::{
+    error::{
+        ConfigSnafu, DirectiveSnafu, FormatError, IoSnafu,
+        UnknownConfigKeySnafu,
+    },
+    ignore,
+};
+
+pub mod discover;
+pub mod editorconfig;
+
 mod string16 {
     pub type String16 = u128;
 
@@ -107,11 +127,11 @@ impl fmt::Display for BoundedIntegerParseError {
 /// `BoundedConfigUsize<1, 5, 1, { string16("error count") }>`, which is a
 /// `usize` bounded between `1` and `5`, with [`Default`] value `1`, and in
 /// units of "error count".
-#[derive(Derivative, Deserialize)]
+#[derive(Derivative, Deserialize, Serialize)]
 #[derivative(Default)]
 #[derivative(Clone)]
 #[derivative(Copy)]
-#[serde(try_from = "usize")]
+#[serde(try_from = "usize", into = "usize")]
 pub struct BoundedConfigUsize<
     const LOWER_BOUND: usize,
     const UPPER_BOUND: usize,
@@ -177,9 +197,144 @@ pub enum FunctionSignatureStyle {
     Tall,
 }
 
+/// Where the `-> ReturnType` of a unit head is placed when the parameter list
+/// has to be broken across lines.
+#[derive(
+    Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnTypeBreak {
+    /// Keep `-> ReturnType` on the same line as the closing parenthesis.
+    #[default]
+    Attached,
+    /// Move `-> ReturnType` onto its own, indented line.
+    OwnLine,
+}
+
+/// When comma-separated groups end with a trailing comma.
+#[derive(
+    Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrailingComma {
+    /// Always end with a trailing comma, even on a single line.
+    Always,
+    /// Never end with a trailing comma.
+    Never,
+    /// End with a trailing comma only when broken across lines.
+    #[default]
+    MultilineOnly,
+}
+
+/// Which line ending to write.
+#[derive(
+    Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum NewlineStyle {
+    /// `\n`.
+    #[default]
+    Unix,
+    /// `\r\n`.
+    Windows,
+    /// The line ending of the platform `spadefmt` runs on.
+    Native,
+    /// The line ending of the first line of the input.
+    Auto,
+}
+
+/// Where the `{` opening the body of a unit, impl block, or match goes.
+#[derive(
+    Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum BraceStyle {
+    /// Keep the `{` at the end of the header line.
+    #[default]
+    SameLine,
+    /// Move the `{` onto its own line below the header.
+    NextLine,
+}
+
+/// How array literals that do not fit on one line are broken.
+#[derive(
+    Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArrayLayout {
+    /// One element per line.
+    #[default]
+    Vertical,
+    /// As many elements per line as fit, right-aligned in columns.
+    Fill,
+    /// [`ArrayLayout::Fill`] for arrays of only literals and names, such as
+    /// lookup tables, and [`ArrayLayout::Vertical`] otherwise.
+    Auto,
+}
+
+/// How to write the letter digits of hexadecimal integer literals.
+#[derive(
+    Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum HexLiteralCase {
+    /// Keep the case used in the source.
+    #[default]
+    Preserve,
+    /// `0xFF`.
+    Upper,
+    /// `0xff`.
+    Lower,
+}
+
+/// What to do about text that cannot fit within the maximum width even at the
+/// start of a line, such as a very long name or string.
+#[derive(
+    Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// Break the groups around it as if a better layout could make it fit.
+    #[default]
+    PreferBreak,
+    /// Lay out the rest of its line as if it fit.
+    Allow,
+    /// Fail to format the file.
+    Error,
+}
+
+/// Maximum line lengths for specific constructs, overriding
+/// [`Config::max_width`] within them.
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConstructMaxWidth {
+    /// For `use` statements.
+    #[serde(default, rename = "use")]
+    pub use_statement: Option<
+        BoundedConfigUsize<
+            1,
+            { usize::MAX },
+            100,
+            { string16("character count") },
+        >,
+    >,
+
+    /// For unit signatures, up to the body.
+    #[serde(default)]
+    pub signature: Option<
+        BoundedConfigUsize<
+            1,
+            { usize::MAX },
+            100,
+            { string16("character count") },
+        >,
+    >,
+}
+
 /// Configures the behavior of `spadefmt`.
-#[derive(Derivative, Deserialize, Debug)]
+#[derive(Derivative, Deserialize, Serialize, Debug, Clone)]
 #[derivative(Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     /// The maximum line length `spadefmt` should aim for.
     #[serde(default)]
@@ -190,6 +345,16 @@ pub struct Config {
         { string16("character count") },
     >,
 
+    /// What to do about text too wide to fit within `max_width` in any
+    /// layout.
+    #[serde(default)]
+    pub overflow: Overflow,
+
+    /// Maximum line lengths for specific constructs instead of `max_width`,
+    /// e.g., `construct_max_width.use = 120`.
+    #[serde(default)]
+    pub construct_max_width: ConstructMaxWidth,
+
     /// The amount of spaces to indent a line.
     #[serde(default)]
     pub indent: BoundedConfigUsize<
@@ -198,4 +363,350 @@ pub struct Config {
         4,
         { string16("character count") },
     >,
+
+    /// The most consecutive blank lines to keep from the source.
+    #[serde(default)]
+    pub max_blank_lines: BoundedConfigUsize<
+        0,
+        { usize::MAX },
+        1,
+        { string16("blank line count") },
+    >,
+
+    /// Where the return type goes when the unit signature is tall.
+    #[serde(default)]
+    pub return_type_break: ReturnTypeBreak,
+
+    /// When to end comma-separated groups with a trailing comma.
+    #[serde(default)]
+    pub trailing_comma: TrailingComma,
+
+    /// Where to put the `{` opening unit, impl block, and match bodies.
+    #[serde(default)]
+    pub brace_style: BraceStyle,
+
+    /// Whether to sort runs of consecutive `use` statements, grouped by
+    /// whether they import from `lib`, `std`, or elsewhere.
+    #[serde(default)]
+    pub reorder_imports: bool,
+
+    /// Whether to shorten `name: name` in named arguments and named argument
+    /// patterns to just `name`.
+    #[serde(default)]
+    pub use_field_shorthand: bool,
+
+    /// How to break array literals that do not fit on one line.
+    #[serde(default)]
+    pub array_layout: ArrayLayout,
+
+    /// How to write the letter digits of hexadecimal integer literals.
+    /// Integer literals otherwise keep the radix they are written in.
+    #[serde(default)]
+    pub hex_literal_case: HexLiteralCase,
+
+    /// If nonzero, integer literal digits are regrouped with `_` separators
+    /// every this many digits from the right; if zero, separators are kept as
+    /// written.
+    #[serde(default)]
+    pub literal_separators:
+        BoundedConfigUsize<0, { usize::MAX }, 0, { string16("digit count") }>,
+
+    /// Which line ending to write.
+    #[serde(default)]
+    pub newline_style: NewlineStyle,
+
+    /// Whether to always end the output with a line ending. If not set, the
+    /// output ends with one exactly when the input does.
+    #[derivative(Default(value = "true"))]
+    #[serde(default = "default_ensure_final_newline")]
+    pub ensure_final_newline: bool,
+
+    /// With `--advise`, the formatted line count above which a file is
+    /// flagged as a candidate for splitting.
+    #[serde(default)]
+    pub max_file_lines:
+        BoundedConfigUsize<1, { usize::MAX }, 1000, { string16("line count") }>,
+
+    /// With `--advise`, the formatted line count above which a top-level item
+    /// is flagged as a candidate for splitting.
+    #[serde(default)]
+    pub max_item_lines:
+        BoundedConfigUsize<1, { usize::MAX }, 200, { string16("line count") }>,
+
+    /// Whether to only fix indentation, spacing, and overlong lines while
+    /// keeping every line break already present in the source.
+    #[serde(default)]
+    pub minimal: bool,
+
+    /// Whether to put exactly one space between `///` and the text of doc
+    /// comments, outside of code blocks.
+    #[serde(default)]
+    pub normalize_doc_comments: bool,
+
+    /// Whether to reflow the paragraphs of doc comments to fill lines up to
+    /// `comment_width`, outside of code blocks.
+    #[serde(default)]
+    pub wrap_doc_comments: bool,
+
+    /// The maximum line length reflowed doc comments should aim for, if not
+    /// `max_width`.
+    #[serde(default)]
+    pub comment_width: Option<
+        BoundedConfigUsize<
+            1,
+            { usize::MAX },
+            100,
+            { string16("character count") },
+        >,
+    >,
+
+    /// With `--color-output`, the theme file to highlight with, relative to
+    /// this configuration file. See [`crate::highlight::Theme`].
+    #[serde(default)]
+    pub theme: Option<String>,
+
+    /// Files to skip, as patterns in `.gitignore` syntax relative to this
+    /// configuration file. See [`crate::ignore`].
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+fn default_ensure_final_newline() -> bool {
+    true
+}
+
+/// The start of a comment on the first line of a file that overrides options
+/// for that file only. See [`Config::with_directive`].
+pub const DIRECTIVE_PREFIX: &str = "// spadefmt:";
+
+impl Config {
+    /// Reads and decodes the configuration file at `path`.
+    pub fn from_path(path: &Utf8Path) -> Result<Self, FormatError> {
+        Self::from_path_over(path, toml::Table::new())
+    }
+
+    /// Reads and decodes the configuration file at `path`, taking any option
+    /// it does not set from `base` before falling back to the default.
+    pub fn from_path_over(
+        path: &Utf8Path,
+        base: toml::Table,
+    ) -> Result<Self, FormatError> {
+        Self::from_table_over(path, Self::read_table(path)?, base)
+    }
+
+    /// Decodes `table`, read from the configuration file at `path` by
+    /// [`Self::read_table`], taking any option it does not set from `base`
+    /// before falling back to the default.
+    pub fn from_table_over(
+        path: &Utf8Path,
+        table: toml::Table,
+        mut base: toml::Table,
+    ) -> Result<Self, FormatError> {
+        merge_table(&mut base, table);
+        toml::Value::Table(base)
+            .try_into::<Self>()
+            .context(ConfigSnafu { path })
+    }
+
+    /// Reads the configuration file at `path` as a table, checking that it
+    /// only sets options that exist. Paths and patterns in it are made
+    /// relative to the working directory instead of to the file, so that
+    /// tables read from different files can be merged.
+    pub fn read_table(path: &Utf8Path) -> Result<toml::Table, FormatError> {
+        let contents = fs::read_to_string(path).context(IoSnafu { path })?;
+        let mut table = toml::from_str::<toml::Table>(&contents)
+            .context(ConfigSnafu { path })?;
+        check_keys::<Self>(path, &table, "")?;
+        if let Some(toml::Value::Table(construct_max_width)) =
+            table.get("construct_max_width")
+        {
+            check_keys::<ConstructMaxWidth>(
+                path,
+                construct_max_width,
+                "construct_max_width.",
+            )?;
+        }
+
+        let directory = path.parent().unwrap_or(Utf8Path::new(""));
+        if let Some(toml::Value::String(theme)) = table.get_mut("theme") {
+            *theme = directory.join(&*theme).into_string();
+        }
+        if let Some(toml::Value::Array(patterns)) = table.get_mut("ignore") {
+            for pattern in patterns {
+                if let toml::Value::String(pattern) = pattern {
+                    *pattern = ignore::anchor(directory, pattern);
+                }
+            }
+        }
+        Ok(table)
+    }
+
+    /// This configuration with the options set by a directive on the first
+    /// line of `source`, if it has one, overriding it for that file only.
+    ///
+    /// The directive is a comment starting with [`DIRECTIVE_PREFIX`] and
+    /// followed by options separated by whitespace, each written
+    /// `key=value` with a TOML value, e.g., `// spadefmt: max_width=120`.
+    pub fn with_directive(&self, source: &str) -> Result<Self, FormatError> {
+        let first_line = source.lines().next().unwrap_or_default();
+        // `// spadefmt::skip` and the like are markers, not directives
+        let Some(options) = first_line
+            .trim_start()
+            .strip_prefix(DIRECTIVE_PREFIX)
+            .filter(|options| !options.starts_with(':'))
+        else {
+            return Ok(self.clone());
+        };
+
+        let mut config = self.clone();
+        let mut table = toml::from_str::<toml::Table>(&self.to_toml())
+            .expect("config is always representable in TOML");
+        for option in options.split_whitespace() {
+            let start = option.as_ptr() as usize - first_line.as_ptr() as usize;
+            let fail = |message: String| {
+                DirectiveSnafu {
+                    columns: start + 1..start + option.len() + 1,
+                    message,
+                }
+                .fail()
+            };
+
+            let Some((key, value)) = option.split_once('=') else {
+                return fail(format!("expected `key=value`, found `{option}`"));
+            };
+            let fields = field_names::<Self>();
+            if !fields.contains(&key) {
+                return fail(match closest(key, fields) {
+                    Some(field) => format!(
+                        "unknown option `{key}`: did you mean `{field}`?"
+                    ),
+                    None => format!("unknown option `{key}`"),
+                });
+            }
+            let Some(value) =
+                toml::from_str::<toml::Table>(&format!("value = {value}"))
+                    .ok()
+                    .and_then(|mut value_table| value_table.remove("value"))
+            else {
+                return fail(format!("`{value}` is not a TOML value"));
+            };
+
+            table.insert(key.to_string(), value);
+            config = match toml::Value::Table(table.clone()).try_into() {
+                Ok(config) => config,
+                Err(error) => return fail(error.message().to_string()),
+            };
+        }
+        Ok(config)
+    }
+
+    /// The configuration as the contents of a configuration file setting
+    /// every option.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("config is always representable in TOML")
+    }
+}
+
+/// Sets the options of `table` that `overrides` sets to its values, merging
+/// tables of options key by key.
+pub fn merge_table(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (
+                Some(toml::Value::Table(options)),
+                toml::Value::Table(option_overrides),
+            ) => merge_table(options, option_overrides),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Fails on the first key of `table`, a table read from the configuration
+/// file at `path`, that is not a field of `T`, suggesting the field it was
+/// likely a misspelling of. `prefix` is prepended to keys in the error.
+fn check_keys<'de, T: Deserialize<'de>>(
+    path: &Utf8Path,
+    table: &toml::Table,
+    prefix: &str,
+) -> Result<(), FormatError> {
+    let fields = field_names::<T>();
+    for key in table.keys() {
+        ensure!(
+            fields.contains(&key.as_str()),
+            UnknownConfigKeySnafu {
+                path,
+                key: format!("{prefix}{key}"),
+                suggestion: closest(key, fields)
+                    .map(|field| format!("{prefix}{field}")),
+            }
+        );
+    }
+    Ok(())
+}
+
+/// The field names of `T`, a struct with named fields.
+fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    /// Records the fields a struct asks to be deserialized from instead of
+    /// deserializing anything.
+    struct FieldNames(&'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for &mut FieldNames {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(
+            self,
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("expected a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0 = fields;
+            Err(de::Error::custom("only recording field names"))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str
+            string bytes byte_buf option unit unit_struct newtype_struct seq
+            tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut field_names = FieldNames(&[]);
+    let _ = T::deserialize(&mut field_names);
+    field_names.0
+}
+
+/// The candidate closest to `key` by edit distance, if it is close enough to
+/// be a likely misspelling.
+fn closest(key: &str, candidates: &[&'static str]) -> Option<&'static str> {
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(key, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= (key.len() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between `a` and `b`, counting characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            current[j + 1] = (previous[j] + usize::from(a_char != *b_char))
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
 }
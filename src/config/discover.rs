@@ -0,0 +1,141 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Finding the configuration file that applies to a source file.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use snafu::ResultExt;
+
+use super::{Config, editorconfig, merge_table};
+use crate::error::{ConfigSnafu, FormatError};
+
+/// The name of the configuration file searched for by [`discover`].
+pub const CONFIG_FILE_NAME: &str = "spadefmt.toml";
+
+/// Finds the nearest [`CONFIG_FILE_NAME`] in `directory` or one of its
+/// ancestors.
+pub fn find_config_file(directory: &Utf8Path) -> Option<Utf8PathBuf> {
+    let directory = if directory.as_str().is_empty() {
+        Utf8Path::new(".")
+    } else {
+        directory
+    };
+    let directory = directory
+        .canonicalize_utf8()
+        .unwrap_or_else(|_| directory.to_path_buf());
+    directory
+        .ancestors()
+        .map(|ancestor| ancestor.join(CONFIG_FILE_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Finds every [`CONFIG_FILE_NAME`] in `directory` and its ancestors, from
+/// the outermost to the nearest.
+pub fn find_config_files(directory: &Utf8Path) -> Vec<Utf8PathBuf> {
+    let Some(nearest) = find_config_file(directory) else {
+        return vec![];
+    };
+    // the nearest file and its directory are the first two ancestors
+    let mut config_files = nearest
+        .ancestors()
+        .skip(2)
+        .map(|ancestor| ancestor.join(CONFIG_FILE_NAME))
+        .filter(|candidate| candidate.is_file())
+        .collect::<Vec<_>>();
+    config_files.reverse();
+    config_files.push(nearest);
+    config_files
+}
+
+/// The options merged from the configuration files of each directory, keyed
+/// by its nearest configuration file, so that the files of a directory only
+/// read and merge its configuration files once.
+///
+/// Nothing is ever invalidated, so a cache should only live as long as the
+/// configuration files can be assumed not to change, e.g., for one run over a
+/// list of files. [`discover`] uses a fresh one every time.
+#[derive(Default)]
+pub struct ConfigCache {
+    merged: Mutex<HashMap<Utf8PathBuf, toml::Table>>,
+}
+
+impl ConfigCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the configuration for formatting `file` like [`discover`],
+    /// reusing the options merged for an earlier file in the same directory.
+    pub fn discover(
+        &self,
+        file: &Utf8Path,
+        config_path: Option<&Utf8Path>,
+    ) -> Result<Config, FormatError> {
+        let editorconfig_settings = editorconfig::settings_for(file)?;
+        if let Some(path) = config_path {
+            return Config::from_path_over(path, editorconfig_settings);
+        }
+        let directory = file.parent().unwrap_or(Utf8Path::new("."));
+        let config_files = find_config_files(directory);
+        match config_files.last() {
+            Some(nearest) => Config::from_table_over(
+                nearest,
+                self.merged_config_table(&config_files)?,
+                editorconfig_settings,
+            ),
+            None => toml::Value::Table(editorconfig_settings)
+                .try_into()
+                .context(ConfigSnafu {
+                    path: editorconfig::EDITORCONFIG_FILE_NAME,
+                }),
+        }
+    }
+
+    /// The options set by the configuration files `config_files`, from the
+    /// outermost to the nearest, each overriding the ones before it.
+    fn merged_config_table(
+        &self,
+        config_files: &[Utf8PathBuf],
+    ) -> Result<toml::Table, FormatError> {
+        let nearest = config_files.last().expect("no configuration files");
+        if let Some(table) = self.merged.lock().unwrap().get(nearest) {
+            return Ok(table.clone());
+        }
+        let mut table = toml::Table::new();
+        for path in config_files {
+            merge_table(&mut table, Config::read_table(path)?);
+        }
+        self.merged
+            .lock()
+            .unwrap()
+            .insert(nearest.clone(), table.clone());
+        Ok(table)
+    }
+}
+
+/// Loads the configuration for formatting `file`, reading every configuration
+/// file anew.
+///
+/// An explicit `config_path` takes precedence. Otherwise, every configuration
+/// file found by [`find_config_files`] from the directory of `file` applies,
+/// with nearer ones overriding the options set by those further out. Options
+/// no configuration file sets are taken from any `.editorconfig` settings
+/// applying to `file`, and otherwise default.
+pub fn discover(
+    file: &Utf8Path,
+    config_path: Option<&Utf8Path>,
+) -> Result<Config, FormatError> {
+    ConfigCache::new().discover(file, config_path)
+}
@@ -0,0 +1,191 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Reading the subset of [EditorConfig](https://editorconfig.org) that maps
+//! onto `spadefmt` options.
+
+use std::{collections::HashMap, fs};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use snafu::ResultExt;
+
+use crate::{
+    error::{FormatError, IoSnafu},
+    glob,
+};
+
+/// The name of the files read by [`settings_for`].
+pub const EDITORCONFIG_FILE_NAME: &str = ".editorconfig";
+
+/// A parsed `.editorconfig` file.
+struct EditorConfig {
+    directory: Utf8PathBuf,
+    root: bool,
+    /// The glob and properties of each section, in file order.
+    sections: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl EditorConfig {
+    fn parse(directory: Utf8PathBuf, contents: &str) -> Self {
+        let mut root = false;
+        let mut sections: Vec<(String, Vec<(String, String)>)> = vec![];
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(glob) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                sections.push((glob.to_string(), vec![]));
+            } else if let Some((key, value)) = line.split_once('=') {
+                let key = key.trim().to_lowercase();
+                let value = value.trim().to_string();
+                match sections.last_mut() {
+                    Some((_, properties)) => properties.push((key, value)),
+                    None if key == "root" => {
+                        root = value.eq_ignore_ascii_case("true");
+                    }
+                    None => {}
+                }
+            }
+        }
+        Self {
+            directory,
+            root,
+            sections,
+        }
+    }
+}
+
+/// Reads the `.editorconfig` files in `directory` and its ancestors, nearest
+/// last, up to and including the first one declaring `root = true`.
+fn editorconfigs_for(
+    directory: &Utf8Path,
+) -> Result<Vec<EditorConfig>, FormatError> {
+    let mut editorconfigs = vec![];
+    for ancestor in directory.ancestors() {
+        let path = ancestor.join(EDITORCONFIG_FILE_NAME);
+        if !path.is_file() {
+            continue;
+        }
+        let contents = fs::read_to_string(&path).context(IoSnafu { path })?;
+        let editorconfig = EditorConfig::parse(ancestor.into(), &contents);
+        let root = editorconfig.root;
+        editorconfigs.push(editorconfig);
+        if root {
+            break;
+        }
+    }
+    editorconfigs.reverse();
+    Ok(editorconfigs)
+}
+
+/// The EditorConfig properties that apply to `file`, with keys lowercased.
+/// Nearer files and later sections take precedence.
+pub fn properties_for(
+    file: &Utf8Path,
+) -> Result<HashMap<String, String>, FormatError> {
+    let directory = match file.parent() {
+        Some(parent) if !parent.as_str().is_empty() => parent,
+        _ => Utf8Path::new("."),
+    };
+    let directory = directory
+        .canonicalize_utf8()
+        .unwrap_or_else(|_| directory.to_path_buf());
+    let file = directory.join(file.file_name().unwrap_or_default());
+
+    let mut properties = HashMap::new();
+    for editorconfig in editorconfigs_for(&directory)? {
+        let Ok(relative_path) = file.strip_prefix(&editorconfig.directory)
+        else {
+            continue;
+        };
+        for (glob, section_properties) in &editorconfig.sections {
+            if section_matches(glob, relative_path.as_str()) {
+                properties.extend(section_properties.iter().cloned());
+            }
+        }
+    }
+    Ok(properties)
+}
+
+/// The `spadefmt.toml` settings equivalent to the EditorConfig properties that
+/// apply to `file`. Properties with no equivalent or unsupported values (such
+/// as `indent_size = tab`) are ignored.
+pub fn settings_for(file: &Utf8Path) -> Result<toml::Table, FormatError> {
+    let properties = properties_for(file)?;
+
+    let mut settings = toml::Table::new();
+    let mut integer = |property: &str, setting: &str| {
+        if let Some(value) = properties
+            .get(property)
+            .and_then(|value| value.parse::<i64>().ok())
+        {
+            settings.insert(setting.into(), toml::Value::Integer(value));
+        }
+    };
+    integer("indent_size", "indent");
+    integer("max_line_length", "max_width");
+
+    match properties.get("end_of_line").map(String::as_str) {
+        Some("lf") => {
+            settings.insert("newline_style".into(), "unix".into());
+        }
+        Some("crlf") => {
+            settings.insert("newline_style".into(), "windows".into());
+        }
+        _ => {}
+    }
+    if let Some(value) = properties
+        .get("insert_final_newline")
+        .and_then(|value| value.parse::<bool>().ok())
+    {
+        settings.insert("ensure_final_newline".into(), value.into());
+    }
+
+    Ok(settings)
+}
+
+/// Whether the section header `glob` of an `.editorconfig` matches the file
+/// at `relative_path` from that `.editorconfig`'s directory.
+fn section_matches(glob: &str, relative_path: &str) -> bool {
+    // a glob without a slash matches the file name in any directory
+    let glob = match glob.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if glob.contains('/') => glob.to_string(),
+        None => format!("**/{glob}"),
+    };
+    let relative_path = relative_path.replace('\\', "/");
+    expand_braces(&glob)
+        .iter()
+        .any(|alternative| glob::matches(alternative, &relative_path))
+}
+
+/// Expands every `{a,b,...}` in `glob` into the alternatives it stands for.
+fn expand_braces(glob: &str) -> Vec<String> {
+    let Some(open) = glob.find('{') else {
+        return vec![glob.to_string()];
+    };
+    let Some(close) = glob[open..].find('}').map(|close| open + close) else {
+        return vec![glob.to_string()];
+    };
+    let (prefix, suffix) = (&glob[..open], &glob[close + 1..]);
+    glob[open + 1..close]
+        .split(',')
+        .flat_map(|alternative| {
+            expand_braces(&format!("{prefix}{alternative}{suffix}"))
+        })
+        .collect()
+}
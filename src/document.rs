@@ -14,20 +14,37 @@
 use std::{
     collections::HashMap,
     fmt::{self, Write},
+    mem,
+    ops::Range,
 };
 
 use inform::common::IndentWriterCommon;
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+use crate::indent_writer::IndentWriter;
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct DocumentIdx(usize);
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 pub enum Document {
     Newline,
+    /// Text on a single line.
     Text(String),
+    /// Source text copied as is, whose lines after the first are printed
+    /// without indentation.
+    Verbatim(String),
     Nest(DocumentIdx, isize),
     Flatten(DocumentIdx),
     List(Vec<DocumentIdx>),
+    /// Items each placed after a space on the current line if they fit there,
+    /// and otherwise on a new line.
+    Fill(Vec<DocumentIdx>),
+    /// A body resolved to fit this many columns instead of the maximum width
+    /// around it.
+    WithWidth(usize, DocumentIdx),
+    /// A body built from this byte range of the source. Resolution keeps it
+    /// around the resolved body.
+    Spanned(Range<usize>, DocumentIdx),
     TryCatch(DocumentIdx, DocumentIdx),
 }
 
@@ -56,57 +73,279 @@ impl InternedDocumentStore {
     pub fn get_mut(&mut self, idx: DocumentIdx) -> &mut Document {
         &mut self.documents[idx.0]
     }
+
+    /// The number of distinct documents interned in this store.
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Drops every document not reachable from `roots`, such as the
+    /// alternatives discarded by [`crate::resolve_try_catch`], so that a
+    /// long-lived store does not keep growing. Returns the new indices of
+    /// `roots`, in order; every other index into this store is invalidated.
+    pub fn compact(&mut self, roots: &[DocumentIdx]) -> Vec<DocumentIdx> {
+        let mut old_documents = mem::take(&mut self.documents);
+        self.inverse.clear();
+
+        // children are added before their parents, so visiting in post-order
+        // lets each document be re-added with its children already remapped
+        let mut remapped = vec![None::<DocumentIdx>; old_documents.len()];
+        let mut stack =
+            roots.iter().map(|root| (*root, false)).collect::<Vec<_>>();
+        while let Some((old_idx, children_done)) = stack.pop() {
+            if remapped[old_idx.0].is_some() {
+                continue;
+            }
+            let document = &old_documents[old_idx.0];
+            if !children_done {
+                stack.push((old_idx, true));
+                match document {
+                    Document::Newline
+                    | Document::Text(_)
+                    | Document::Verbatim(_) => {}
+                    Document::Nest(body_idx, _)
+                    | Document::Flatten(body_idx)
+                    | Document::WithWidth(_, body_idx)
+                    | Document::Spanned(_, body_idx) => {
+                        stack.push((*body_idx, false));
+                    }
+                    Document::List(children) | Document::Fill(children) => {
+                        stack.extend(
+                            children.iter().rev().map(|child| (*child, false)),
+                        )
+                    }
+                    Document::TryCatch(try_body_idx, catch_body_idx) => {
+                        stack.extend([
+                            (*catch_body_idx, false),
+                            (*try_body_idx, false),
+                        ]);
+                    }
+                }
+                continue;
+            }
+
+            let new = |idx: &DocumentIdx| {
+                remapped[idx.0].expect("children are remapped before parents")
+            };
+            let document = match mem::replace(
+                &mut old_documents[old_idx.0],
+                Document::Newline,
+            ) {
+                Document::Nest(body_idx, by) => {
+                    Document::Nest(new(&body_idx), by)
+                }
+                Document::Flatten(body_idx) => {
+                    Document::Flatten(new(&body_idx))
+                }
+                Document::WithWidth(width, body_idx) => {
+                    Document::WithWidth(width, new(&body_idx))
+                }
+                Document::Spanned(span, body_idx) => {
+                    Document::Spanned(span, new(&body_idx))
+                }
+                Document::List(children) => {
+                    Document::List(children.iter().map(new).collect())
+                }
+                Document::Fill(children) => {
+                    Document::Fill(children.iter().map(new).collect())
+                }
+                Document::TryCatch(try_body_idx, catch_body_idx) => {
+                    Document::TryCatch(new(&try_body_idx), new(&catch_body_idx))
+                }
+                leaf => leaf,
+            };
+            remapped[old_idx.0] = Some(self.add(document));
+        }
+
+        roots
+            .iter()
+            .map(|root| remapped[root.0].expect("roots are remapped"))
+            .collect()
+    }
+}
+
+/// A pending step of [`print_resolved`], which keeps its own stack so that
+/// deeply nested documents cannot overflow the call stack.
+enum PrintStep {
+    Print(DocumentIdx, bool),
+    Unindent(isize),
+    Space,
 }
 
 pub fn print_resolved<W: fmt::Write>(
     store: &InternedDocumentStore,
-    f: &mut inform::fmt::IndentWriter<W>,
+    f: &mut IndentWriter<W>,
     idx: DocumentIdx,
     flattened: bool,
     last_was_newline: &mut bool,
 ) -> fmt::Result {
-    let last_was_newline_old = *last_was_newline;
-    *last_was_newline = false;
-    match store.get(idx) {
-        Document::Newline => {
-            if flattened {
-                if !last_was_newline_old {
-                    write!(f, " ")?;
+    let mut stack = vec![PrintStep::Print(idx, flattened)];
+    while let Some(step) = stack.pop() {
+        let (idx, flattened) = match step {
+            PrintStep::Print(idx, flattened) => (idx, flattened),
+            PrintStep::Unindent(by) => {
+                f.indent_by(-by)
+                    .expect("undoing a nest cannot make the indent negative");
+                continue;
+            }
+            PrintStep::Space => {
+                write!(f, " ")?;
+                *last_was_newline = false;
+                continue;
+            }
+        };
+
+        let last_was_newline_old = *last_was_newline;
+        *last_was_newline = false;
+        match store.get(idx) {
+            Document::Newline => {
+                if flattened {
+                    if !last_was_newline_old {
+                        write!(f, " ")?;
+                    }
+                } else {
+                    writeln!(f)?;
                 }
-            } else {
-                writeln!(f)?;
+                *last_was_newline = true;
+            }
+            Document::Text(text) => write!(f, "{text}")?,
+            Document::Verbatim(text) => f.write_verbatim(text)?,
+            Document::Nest(body_idx, by) => {
+                if f.indent_by(*by).is_none() {
+                    panic!(
+                        "{}",
+                        ResolvedInvariantViolation::NegativeIndent(
+                            idx,
+                            f.indent() as isize + by
+                        )
+                    );
+                }
+                stack.push(PrintStep::Unindent(*by));
+                stack.push(PrintStep::Print(*body_idx, flattened));
+            }
+            Document::Flatten(body_idx) => {
+                stack.push(PrintStep::Print(*body_idx, true));
+            }
+            Document::WithWidth(_, body_idx)
+            | Document::Spanned(_, body_idx) => {
+                stack.push(PrintStep::Print(*body_idx, flattened));
+            }
+            Document::List(children) => {
+                stack.extend(
+                    children
+                        .iter()
+                        .rev()
+                        .map(|child| PrintStep::Print(*child, flattened)),
+                );
+            }
+            // a fill that was never resolved keeps every item on one line
+            Document::Fill(children) => {
+                if let Some((first, rest)) = children.split_first() {
+                    for child in rest.iter().rev() {
+                        stack.push(PrintStep::Print(*child, flattened));
+                        stack.push(PrintStep::Space);
+                    }
+                    stack.push(PrintStep::Print(*first, flattened));
+                }
+            }
+            Document::TryCatch(_, _) => {
+                panic!(
+                    "{}",
+                    ResolvedInvariantViolation::UnresolvedTryCatch(idx)
+                )
             }
-            *last_was_newline = true;
-            Ok(())
-        }
-        Document::Text(text) => write!(f, "{text}"),
-        Document::Nest(body_idx, by) => {
-            // TODO: extend indent formatter
-            if *by > 0 {
-                f.increase_indent();
-            } else {
-                f.decrease_indent();
-            }
-            print_resolved(store, f, *body_idx, flattened, last_was_newline)?;
-            if *by > 0 {
-                f.decrease_indent();
-            } else {
-                f.increase_indent();
-            }
-            Ok(())
-        }
-        Document::Flatten(body_idx) => {
-            print_resolved(store, f, *body_idx, true, last_was_newline)
         }
-        Document::List(children) => {
-            children.iter().copied().try_for_each(|child| {
-                print_resolved(store, f, child, flattened, last_was_newline)
-            })
+    }
+    Ok(())
+}
+
+/// A way in which a document fails to be fully resolved, i.e., ready for
+/// [`print_resolved`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ResolvedInvariantViolation {
+    /// The document at this index is a [`Document::TryCatch`] that was never
+    /// resolved to one of its alternatives.
+    UnresolvedTryCatch(DocumentIdx),
+    /// The [`Document::Nest`] at this index brings the net indentation to the
+    /// given negative amount.
+    NegativeIndent(DocumentIdx, isize),
+    /// The [`Document::Verbatim`] at this index spans lines but is inside a
+    /// [`Document::Flatten`], which is meant to be laid out on one line.
+    HardLineInFlatten(DocumentIdx),
+}
+
+impl fmt::Display for ResolvedInvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnresolvedTryCatch(idx) => write!(
+                f,
+                "TryCatch {idx:?} found in resolved document (was \
+                 resolve_try_catch run?)"
+            ),
+            Self::NegativeIndent(idx, net) => write!(
+                f,
+                "Nest {idx:?} brings the net indentation to {net}, which is \
+                 negative"
+            ),
+            Self::HardLineInFlatten(idx) => write!(
+                f,
+                "Verbatim {idx:?} spans lines inside a Flatten, which cannot \
+                 break"
+            ),
         }
-        Document::TryCatch(_, _) => {
-            panic!("TryCatch found in resolved document")
+    }
+}
+
+/// Checks that the document at `idx` can be printed by [`print_resolved`]:
+/// no [`Document::TryCatch`] remains, no [`Document::Nest`] takes the
+/// indentation below zero, and no [`Document::Flatten`] holds a
+/// [`Document::Verbatim`] that spans lines.
+pub fn check_resolved(
+    store: &InternedDocumentStore,
+    idx: DocumentIdx,
+) -> Result<(), ResolvedInvariantViolation> {
+    let mut stack = vec![(idx, 0, false)];
+    while let Some((idx, net_indent, flattened)) = stack.pop() {
+        match store.get(idx) {
+            Document::Verbatim(text) if flattened && text.contains('\n') => {
+                return Err(ResolvedInvariantViolation::HardLineInFlatten(idx));
+            }
+            Document::Newline | Document::Text(_) | Document::Verbatim(_) => {}
+            Document::Nest(body_idx, by) => {
+                let net_indent = net_indent + by;
+                if net_indent < 0 {
+                    return Err(ResolvedInvariantViolation::NegativeIndent(
+                        idx, net_indent,
+                    ));
+                }
+                stack.push((*body_idx, net_indent, flattened));
+            }
+            Document::Flatten(body_idx) => {
+                stack.push((*body_idx, net_indent, true))
+            }
+            Document::WithWidth(_, body_idx)
+            | Document::Spanned(_, body_idx) => {
+                stack.push((*body_idx, net_indent, flattened))
+            }
+            Document::List(children) | Document::Fill(children) => stack
+                .extend(
+                    children
+                        .iter()
+                        .rev()
+                        .map(|child| (*child, net_indent, flattened)),
+                ),
+            Document::TryCatch(_, _) => {
+                return Err(ResolvedInvariantViolation::UnresolvedTryCatch(
+                    idx,
+                ));
+            }
         }
     }
+    Ok(())
 }
 
 pub fn debug_print<W: fmt::Write>(
@@ -117,6 +356,7 @@ pub fn debug_print<W: fmt::Write>(
     match store.get(idx) {
         Document::Newline => write!(f, "Newline"),
         Document::Text(text) => write!(f, "Text(\"{text}\")"),
+        Document::Verbatim(text) => write!(f, "Verbatim({text:?})"),
         Document::Nest(body_idx, by) => {
             writeln!(f, "Nest(")?;
             f.increase_indent();
@@ -146,6 +386,34 @@ pub fn debug_print<W: fmt::Write>(
             f.decrease_indent();
             write!(f, ")")
         }
+        Document::WithWidth(width, body_idx) => {
+            writeln!(f, "WithWidth(")?;
+            f.increase_indent();
+            writeln!(f, "{width},")?;
+            debug_print(store, f, *body_idx)?;
+            writeln!(f)?;
+            f.decrease_indent();
+            write!(f, ")")
+        }
+        Document::Spanned(span, body_idx) => {
+            writeln!(f, "Spanned(")?;
+            f.increase_indent();
+            writeln!(f, "{span:?},")?;
+            debug_print(store, f, *body_idx)?;
+            writeln!(f)?;
+            f.decrease_indent();
+            write!(f, ")")
+        }
+        Document::Fill(children) => {
+            writeln!(f, "Fill(")?;
+            f.increase_indent();
+            for child in children {
+                debug_print(store, f, *child)?;
+                writeln!(f, ",")?;
+            }
+            f.decrease_indent();
+            write!(f, ")")
+        }
         Document::TryCatch(try_body, catch_body) => {
             writeln!(f, "TryCatch(")?;
             f.increase_indent();
@@ -158,3 +426,64 @@ pub fn debug_print<W: fmt::Write>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolved_document_passes() {
+        let mut store = InternedDocumentStore::default();
+        let newline = store.add(Document::Newline);
+        let text = store.add(Document::Text("x".into()));
+        let dedent = store.add(Document::Nest(text, -4));
+        let body = store.add(Document::List(vec![newline, dedent]));
+        let root = store.add(Document::Nest(body, 4));
+
+        assert_eq!(check_resolved(&store, root), Ok(()));
+    }
+
+    #[test]
+    fn unresolved_try_catch_is_reported() {
+        let mut store = InternedDocumentStore::default();
+        let flat = store.add(Document::Text("flat".into()));
+        let broken = store.add(Document::Text("broken".into()));
+        let choice = store.add(Document::TryCatch(flat, broken));
+        let root = store.add(Document::List(vec![flat, choice]));
+
+        assert_eq!(
+            check_resolved(&store, root),
+            Err(ResolvedInvariantViolation::UnresolvedTryCatch(choice))
+        );
+    }
+
+    #[test]
+    fn negative_nest_is_reported() {
+        let mut store = InternedDocumentStore::default();
+        let newline = store.add(Document::Newline);
+        let text = store.add(Document::Text("x".into()));
+        let dedent = store.add(Document::Nest(text, -4));
+        let body = store.add(Document::List(vec![newline, dedent]));
+        let root = store.add(Document::Nest(body, 2));
+
+        assert_eq!(
+            check_resolved(&store, root),
+            Err(ResolvedInvariantViolation::NegativeIndent(dedent, -2))
+        );
+    }
+
+    #[test]
+    fn hard_line_in_flatten_is_reported() {
+        let mut store = InternedDocumentStore::default();
+        let one_line = store.add(Document::Verbatim("x".into()));
+        let two_lines = store.add(Document::Verbatim("x\ny".into()));
+        let body = store.add(Document::List(vec![one_line, two_lines]));
+        let root = store.add(Document::Flatten(body));
+
+        assert_eq!(
+            check_resolved(&store, root),
+            Err(ResolvedInvariantViolation::HardLineInFlatten(two_lines))
+        );
+        assert_eq!(check_resolved(&store, body), Ok(()));
+    }
+}
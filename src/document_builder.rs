@@ -21,19 +21,101 @@ use spade_common::{
 };
 use spade_diagnostics::codespan::Span;
 use spade_parser::lexer;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    config::{
+        ArrayLayout, BraceStyle, Config, HexLiteralCase, ReturnTypeBreak,
+        TrailingComma,
+    },
+    document::{Document, DocumentIdx, InternedDocumentStore},
+};
+
+/// The arrow separating a unit's parameter list from its return type.
+pub const RETURN_TYPE_ARROW: &str = "->";
+
+/// The comment that, on the line right before an item or statement, makes the
+/// formatter copy that item or statement from the source as is.
+pub const SKIP_MARKER: &str = "// spadefmt::skip";
+
+/// The comment that turns formatting off for the items or statements after it,
+/// until a [`FORMAT_ON_MARKER`] or the end of the enclosing block.
+pub const FORMAT_OFF_MARKER: &str = "// spadefmt::off";
 
-use crate::document::{Document, DocumentIdx, InternedDocumentStore};
+/// The comment that turns formatting back on after a [`FORMAT_OFF_MARKER`].
+pub const FORMAT_ON_MARKER: &str = "// spadefmt::on";
 
 pub struct DocumentBuilder<'code> {
+    config: Config,
     indent: isize,
+    always_break: bool,
     file: RefCell<Option<&'code SimpleFile<String, String>>>,
+    /// The lines of the file holding a [`FORMAT_OFF_MARKER`] or
+    /// [`FORMAT_ON_MARKER`], in order, with whether they turn formatting on.
+    format_toggles: RefCell<Vec<(usize, bool)>>,
+    /// Asked in order before each construct is built; see [`LayoutHook`].
+    hooks: Vec<Box<dyn LayoutHook + 'code>>,
     inner: RefCell<InternedDocumentStore>,
 }
 
+/// A run of constructs in a region where formatting is turned off, copied from
+/// the source as is along with the markers around it.
+struct FormatOffRegion {
+    idx: DocumentIdx,
+    /// How many constructs the region covers.
+    length: usize,
+    /// The line of the [`FORMAT_OFF_MARKER`] starting the region.
+    start_line_index: usize,
+    /// The last line the region covers.
+    end_line_index: usize,
+}
+
+/// A construct that can be built into a document, so that it can be laid out
+/// by the grouping helpers of a [`DocumentBuilder`].
+///
+/// Building the same construct twice adds two documents to the store, so
+/// implementations should build each construct once. A document built ahead
+/// of time can be passed where a construct is expected as a
+/// `Loc<DocumentIdx>`, which builds to itself.
 pub trait BuildAsDocument {
     fn build(&self, builder: &DocumentBuilder) -> DocumentIdx;
 }
 
+/// Overrides the layout of constructs for a [`DocumentBuilder`], e.g., for a
+/// tool that expands its own syntax before formatting. Each method returns
+/// the document for a construct, or `None` to leave it to the builder.
+///
+/// A hook is asked before the builder lays out an item, statement, or
+/// expression itself, but after it is found to be skipped or in a region
+/// where formatting is off. It may build the children of a construct through
+/// the builder, but not the construct itself, as that would ask the hook
+/// again.
+pub trait LayoutHook {
+    fn build_item(
+        &self,
+        _builder: &DocumentBuilder,
+        _item: &ast::Item,
+    ) -> Option<DocumentIdx> {
+        None
+    }
+
+    fn build_statement(
+        &self,
+        _builder: &DocumentBuilder,
+        _statement: &Loc<ast::Statement>,
+    ) -> Option<DocumentIdx> {
+        None
+    }
+
+    fn build_expression(
+        &self,
+        _builder: &DocumentBuilder,
+        _expression: &Loc<ast::Expression>,
+    ) -> Option<DocumentIdx> {
+        None
+    }
+}
+
 impl BuildAsDocument for Loc<DocumentIdx> {
     fn build(&self, _builder: &DocumentBuilder) -> DocumentIdx {
         self.inner
@@ -75,20 +157,32 @@ pub type AstParameter =
 
 can_build!(AstParameter: build_parameter);
 
+/// A field of a named argument pattern, e.g., `a` or `b: x` in `T$(a, b: x)`.
+pub type AstNamedPatternArgument = (Loc<Identifier>, Option<Loc<ast::Pattern>>);
+
+can_build!(AstNamedPatternArgument: build_named_pattern_argument);
+can_build!(ast::NamedTurbofish: build_named_turbofish);
+
 can_build!(ast::EnumVariant: build_enum_variant);
+can_build!(ast::WhereClause: build_where_clause);
 
 pub trait HasLineNumber {
     fn line_index(&self, builder: &DocumentBuilder) -> usize;
+
+    /// The line on which this construct ends, which by default is assumed to
+    /// be the line on which it starts.
+    fn end_line_index(&self, builder: &DocumentBuilder) -> usize {
+        self.line_index(builder)
+    }
 }
 
 impl HasLineNumber for Span {
     fn line_index(&self, builder: &DocumentBuilder) -> usize {
-        builder
-            .file
-            .borrow()
-            .unwrap()
-            .line_index((), self.start().to_usize())
-            .expect("span was somehow not from the file it came from")
+        builder.line_index_at(self.start().to_usize())
+    }
+
+    fn end_line_index(&self, builder: &DocumentBuilder) -> usize {
+        builder.line_index_at(self.end().to_usize())
     }
 }
 
@@ -96,6 +190,10 @@ impl<T> HasLineNumber for Loc<T> {
     fn line_index(&self, builder: &DocumentBuilder) -> usize {
         self.span.line_index(builder)
     }
+
+    fn end_line_index(&self, builder: &DocumentBuilder) -> usize {
+        self.span.end_line_index(builder)
+    }
 }
 
 impl HasLineNumber for ast::EnumVariant {
@@ -113,10 +211,36 @@ impl HasLineNumber for ast::NamedArgument {
     }
 }
 
+impl HasLineNumber for AstNamedPatternArgument {
+    fn line_index(&self, builder: &DocumentBuilder) -> usize {
+        self.0.line_index(builder)
+    }
+}
+
+impl HasLineNumber for ast::NamedTurbofish {
+    fn line_index(&self, builder: &DocumentBuilder) -> usize {
+        match self {
+            ast::NamedTurbofish::Short(name)
+            | ast::NamedTurbofish::Full(name, _) => name.line_index(builder),
+        }
+    }
+}
+
+impl HasLineNumber for ast::WhereClause {
+    fn line_index(&self, builder: &DocumentBuilder) -> usize {
+        match self {
+            ast::WhereClause::GenericInt { target, .. }
+            | ast::WhereClause::TraitBounds { target, .. } => {
+                target.line_index(builder)
+            }
+        }
+    }
+}
+
 impl HasLineNumber for AstParameter {
     fn line_index(&self, builder: &DocumentBuilder) -> usize {
         self.0
-             .0
+            .0
             .first()
             .map(|first| first.span)
             .unwrap_or(self.1.span)
@@ -124,7 +248,7 @@ impl HasLineNumber for AstParameter {
     }
 }
 
-fn span_of_item(item: &ast::Item) -> Span {
+pub fn span_of_item(item: &ast::Item) -> Span {
     match item {
         spade_ast::Item::Unit(unit) => unit.span,
         spade_ast::Item::TraitDef(trait_definition) => trait_definition.span,
@@ -136,72 +260,500 @@ fn span_of_item(item: &ast::Item) -> Span {
     }
 }
 
+/// The span of `item` including the attributes before it, which
+/// [`span_of_item`] may leave out.
+pub fn full_span_of_item(item: &ast::Item) -> Span {
+    let span = span_of_item(item);
+    let first_attribute = match item {
+        spade_ast::Item::Unit(unit) => unit.head.attributes.0.first(),
+        _ => None,
+    };
+    match first_attribute {
+        Some(first_attribute) => Span::new(
+            first_attribute.span.start().min(span.start()),
+            span.end(),
+        ),
+        None => span,
+    }
+}
+
+/// How tightly the binary operator spelled `op` binds, following the
+/// precedence table of the Spade parser. Higher binds tighter.
+fn binary_operator_precedence(op: &str) -> u8 {
+    match op {
+        "||" => 1,
+        "&&" => 2,
+        "^^" => 3,
+        "==" | "!=" => 4,
+        "<" | ">" | "<=" | ">=" => 5,
+        "|" => 6,
+        "^" => 7,
+        "&" => 8,
+        "<<" | ">>" | ">>>" => 9,
+        "+" | "-" => 10,
+        "*" | "/" | "%" => 11,
+        _ => 0,
+    }
+}
+
+/// Where a `use` statement is placed when reordering imports: `lib` imports
+/// first, then `std` imports, then everything else.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum UseGroup {
+    Lib,
+    Std,
+    Local,
+}
+
+fn use_group(use_statement: &ast::UseStatement) -> UseGroup {
+    match use_statement
+        .path
+        .0
+        .first()
+        .map(|component| component.to_string())
+        .as_deref()
+    {
+        Some("lib") => UseGroup::Lib,
+        Some("std") => UseGroup::Std,
+        _ => UseGroup::Local,
+    }
+}
+
+fn use_sort_key(
+    use_statement: &ast::UseStatement,
+) -> (UseGroup, String, Option<String>) {
+    (
+        use_group(use_statement),
+        use_statement
+            .path
+            .0
+            .iter()
+            .map(|component| component.to_string())
+            .collect::<Vec<_>>()
+            .join("::"),
+        use_statement.alias.as_ref().map(|alias| alias.to_string()),
+    )
+}
+
+/// Whether `path` is just `name`, so that `name: path` can be shortened to
+/// `name`.
+fn is_single_identifier(path: &Path, name: &Identifier) -> bool {
+    matches!(path.0.as_slice(), [component] if **component == *name)
+}
+
+/// Rewrites the integer literal spelled `text` in the source, keeping its radix
+/// prefix and type suffix, with hexadecimal letter digits in
+/// `hex_literal_case` and, if `literal_separators` is nonzero, a `_` between
+/// every `literal_separators` digits from the right.
+fn format_int_literal(
+    text: &str,
+    hex_literal_case: HexLiteralCase,
+    literal_separators: usize,
+) -> String {
+    let prefix_length = ["0x", "0X", "0b", "0B"]
+        .iter()
+        .find(|prefix| text.starts_with(*prefix))
+        .map_or(0, |prefix| prefix.len());
+    let (prefix, rest) = text.split_at(prefix_length);
+    let is_hex = prefix.eq_ignore_ascii_case("0x");
+    let digits_length = rest
+        .find(|c: char| {
+            !(c == '_' || c.is_ascii_digit() || is_hex && c.is_ascii_hexdigit())
+        })
+        .unwrap_or(rest.len());
+    let (digits, suffix) = rest.split_at(digits_length);
+
+    let digits = match hex_literal_case {
+        HexLiteralCase::Upper if is_hex => digits.to_ascii_uppercase(),
+        HexLiteralCase::Lower if is_hex => digits.to_ascii_lowercase(),
+        _ => digits.to_string(),
+    };
+    let digits = if literal_separators == 0 {
+        digits
+    } else {
+        let bare_digits =
+            digits.chars().filter(|c| *c != '_').collect::<Vec<_>>();
+        let mut grouped = String::new();
+        for (i, digit) in bare_digits.iter().enumerate() {
+            if i > 0 && (bare_digits.len() - i) % literal_separators == 0 {
+                grouped.push('_');
+            }
+            grouped.push(*digit);
+        }
+        grouped
+    };
+
+    format!("{prefix}{digits}{suffix}")
+}
+
+/// Whether `statement` starts a new pipeline stage, i.e., is a `reg;` marker
+/// or a stage label.
+fn is_stage_boundary(statement: &ast::Statement) -> bool {
+    matches!(
+        statement,
+        ast::Statement::PipelineRegMarker(..) | ast::Statement::Label(_)
+    )
+}
+
+/// Whether `expression` is a literal or name, possibly negated, as found in the
+/// cells of a lookup table.
+fn is_table_cell(expression: &ast::Expression) -> bool {
+    match expression {
+        ast::Expression::IntLiteral(_)
+        | ast::Expression::BoolLiteral(_)
+        | ast::Expression::BitLiteral(_)
+        | ast::Expression::Identifier(_) => true,
+        ast::Expression::UnaryOperator(_, inner) => is_table_cell(inner),
+        _ => false,
+    }
+}
+
+/// Whether `expression` is a block with statements, which must never be
+/// flattened onto one line.
+fn is_multiline_block(expression: &ast::Expression) -> bool {
+    matches!(
+        expression,
+        ast::Expression::Block(block) if !block.statements.is_empty()
+    )
+}
+
+/// The text after `///` of each line of a doc comment whose lines have
+/// `contents`. If `normalize` is set, exactly one space separates `///` and
+/// the text of each line; if `width` is given, paragraphs are reflowed to
+/// fill lines of text up to that many columns. Code blocks and lines that
+/// start a Markdown heading, list item, quote, or table are kept as they are.
+fn normalize_doc_comment(
+    contents: &[&str],
+    normalize: bool,
+    width: Option<usize>,
+) -> Vec<String> {
+    let mut lines = vec![];
+    let mut paragraph = vec![];
+    let mut in_code_block = false;
+    for content in contents {
+        let text = content.trim();
+        let is_fence = text.starts_with("```");
+        let is_paragraph_text = !in_code_block
+            && !is_fence
+            && !text.is_empty()
+            && !text.starts_with(['#', '-', '*', '+', '>', '|']);
+        if width.is_some() && is_paragraph_text {
+            paragraph.extend(text.split_whitespace());
+            continue;
+        }
+        if let Some(width) = width {
+            reflow_paragraph(&mut paragraph, width, &mut lines);
+        }
+        if is_fence {
+            in_code_block = !in_code_block;
+        }
+        lines.push(if normalize && (!in_code_block || is_fence) {
+            if text.is_empty() {
+                String::new()
+            } else {
+                format!(" {}", content.trim_start())
+            }
+        } else {
+            content.to_string()
+        });
+    }
+    if let Some(width) = width {
+        reflow_paragraph(&mut paragraph, width, &mut lines);
+    }
+    lines
+}
+
+/// Fills the words of `paragraph` into lines of at most `width` columns,
+/// where a word no line fits gets one of its own, adding them to `lines`
+/// and leaving `paragraph` empty.
+fn reflow_paragraph(
+    paragraph: &mut Vec<&str>,
+    width: usize,
+    lines: &mut Vec<String>,
+) {
+    let mut line = String::new();
+    for word in paragraph.drain(..) {
+        if !line.is_empty() && line.width() + 1 + word.width() > width {
+            lines.push(format!(" {line}"));
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(format!(" {line}"));
+    }
+}
+
 impl<'code> DocumentBuilder<'code> {
-    pub fn new(indent: isize) -> Self {
+    pub fn new(config: &Config) -> Self {
         Self {
-            indent,
+            config: config.clone(),
+            indent: config.indent.inner as isize,
+            always_break: false,
             file: Default::default(),
+            format_toggles: Default::default(),
+            hooks: vec![],
             inner: Default::default(),
         }
     }
 
+    /// Lets `hook` override the layout of constructs, before any hook added
+    /// after it.
+    pub fn hook(mut self, hook: impl LayoutHook + 'code) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// The number of columns a nested block is indented by.
+    pub fn indent(&self) -> isize {
+        self.indent
+    }
+
+    /// The configuration constructs are built according to.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The document built by the first hook that overrides a construct, if
+    /// any.
+    fn run_hooks(
+        &self,
+        build: impl Fn(&dyn LayoutHook) -> Option<DocumentIdx>,
+    ) -> Option<DocumentIdx> {
+        self.hooks.iter().find_map(|hook| build(hook.as_ref()))
+    }
+
+    /// When `always_break` is set, every choice between a flat and a broken
+    /// layout is decided up front in favor of the broken one, so resolution
+    /// never has to explore alternatives.
+    pub fn always_break(mut self, always_break: bool) -> Self {
+        self.always_break = always_break;
+        self
+    }
+
     pub fn build_root(
         self,
         root: &ast::ModuleBody,
         file: &'code SimpleFile<String, String>,
     ) -> (InternedDocumentStore, DocumentIdx) {
-        self.file.replace(Some(file));
+        self.set_file(file);
+        let idx = self.build_items(&root.members, 0);
+        (self.inner.take(), idx)
+    }
+
+    /// Builds each top-level item of `root` on its own, returning one document
+    /// per item in the order of `root.members`. Items in a region where
+    /// formatting is turned off are copied from the source as is.
+    pub fn build_root_items(
+        self,
+        root: &ast::ModuleBody,
+        file: &'code SimpleFile<String, String>,
+    ) -> (InternedDocumentStore, Vec<DocumentIdx>) {
+        self.set_file(file);
+        let mut first_line_index = 0;
+        let idxs = root
+            .members
+            .iter()
+            .map(|item| {
+                let span = full_span_of_item(item);
+                let idx = if self
+                    .format_off_line(span.line_index(self), first_line_index)
+                    .is_some()
+                {
+                    self.verbatim(self.source_text(span).replace("\r\n", "\n"))
+                } else {
+                    self.build_item(item)
+                };
+                first_line_index = span.end_line_index(self) + 1;
+                idx
+            })
+            .collect();
+        (self.inner.take(), idxs)
+    }
+
+    /// Builds `items` one per line, keeping up to `max_blank_lines` of the
+    /// blank lines separating them in the source. If `reorder_imports` is set,
+    /// runs of consecutive `use` statements are sorted and grouped. Only
+    /// format toggles from line `first_line_index` on apply to `items`.
+    pub fn build_items(
+        &self,
+        items: &[ast::Item],
+        mut first_line_index: usize,
+    ) -> DocumentIdx {
         let mut list = vec![];
-        let mut last_line_index = 0;
-        for (i, item) in root.members.iter().enumerate() {
-            let item_line_index = span_of_item(item).line_index(&self);
-            if i > 0 {
-                if last_line_index < item_line_index - 1 {
+        let mut last_end_line_index = None;
+        let mut rest = items;
+        while let Some(first) = rest.first() {
+            if let Some(region) = self.build_format_off_region(
+                rest,
+                full_span_of_item,
+                first_line_index,
+            ) {
+                if let Some(last_end_line_index) = last_end_line_index {
                     list.push(self.newline());
+                    list.extend(self.blank_lines(
+                        last_end_line_index,
+                        region.start_line_index,
+                    ));
                 }
+                list.push(region.idx);
+                rest = &rest[region.length..];
+                last_end_line_index = Some(region.end_line_index);
+                first_line_index = region.end_line_index + 1;
+                continue;
+            }
+
+            let use_run_length = if self.config.reorder_imports {
+                // a use statement turned off for formatting is not moved
+                rest.iter()
+                    .take_while(|item| {
+                        matches!(item, ast::Item::Use(_))
+                            && self
+                                .format_off_line(
+                                    full_span_of_item(item).line_index(self),
+                                    first_line_index,
+                                )
+                                .is_none()
+                    })
+                    .count()
+            } else {
+                0
+            };
+            let chunk_length = use_run_length.max(1);
+            let (chunk, remaining) = rest.split_at(chunk_length);
+            rest = remaining;
+
+            if let Some(last_end_line_index) = last_end_line_index {
                 list.push(self.newline());
+                list.extend(self.blank_lines(
+                    last_end_line_index,
+                    span_of_item(first).line_index(self),
+                ));
             }
-            list.push(self.build_item(item));
-            last_line_index = item_line_index;
+            let _span = tracing::debug_span!(
+                "item",
+                line = span_of_item(first).line_index(self) + 1
+            )
+            .entered();
+            list.push(if use_run_length > 1 {
+                self.build_sorted_uses(chunk)
+            } else {
+                self.build_item(first)
+            });
+            let end_line_index = span_of_item(chunk.last().unwrap_or(first))
+                .end_line_index(self);
+            last_end_line_index = Some(end_line_index);
+            first_line_index = end_line_index + 1;
         }
-        let idx = self.list(list);
-        (self.inner.take(), idx)
+        self.list(list)
+    }
+
+    /// Builds the `use` statements among `items` sorted by path, grouped by
+    /// whether they import from `lib`, `std`, or elsewhere, with a blank line
+    /// between groups.
+    pub fn build_sorted_uses(&self, items: &[ast::Item]) -> DocumentIdx {
+        let mut uses = items
+            .iter()
+            .filter_map(|item| match item {
+                ast::Item::Use(use_statement) => Some(use_statement),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        uses.sort_by_cached_key(|use_statement| use_sort_key(use_statement));
+
+        let mut list = vec![];
+        let mut last_group = None;
+        for use_statement in uses {
+            let group = use_group(use_statement);
+            if let Some(last_group) = last_group {
+                list.push(self.newline());
+                if last_group != group {
+                    list.push(self.newline());
+                }
+            }
+            list.push(self.build_use(use_statement));
+            last_group = Some(group);
+        }
+        self.list(list)
     }
 
     pub fn build_item(&self, item: &ast::Item) -> DocumentIdx {
-        match item {
+        let span = full_span_of_item(item);
+        if self.is_skipped(span.line_index(self)) {
+            return self.build_skipped(span);
+        }
+        if let Some(idx) = self.run_hooks(|hook| hook.build_item(self, item)) {
+            return self.spanned(span, idx);
+        }
+
+        let idx = match item {
             ast::Item::Unit(unit) => self.build_unit(unit),
-            ast::Item::TraitDef(_) => todo!(),
+            ast::Item::TraitDef(trait_definition) => {
+                self.build_trait_definition(trait_definition)
+            }
             ast::Item::Type(type_declaration) => {
                 self.build_type_declaration(type_declaration)
             }
-            ast::Item::ExternalMod(_) => todo!(),
+            ast::Item::ExternalMod(external_module) => {
+                self.text(format!("extern mod {};", external_module.name))
+            }
             ast::Item::Module(module) => self.build_module(module),
             ast::Item::Use(use_statement) => self.build_use(use_statement),
             ast::Item::ImplBlock(impl_block) => {
                 self.build_impl_block(impl_block)
             }
-        }
+        };
+        self.spanned(span, idx)
     }
 
     pub fn build_unit(&self, unit: &Loc<ast::Unit>) -> DocumentIdx {
+        let header_body_separator = match (&unit.body, self.config.brace_style)
+        {
+            (Some(_), BraceStyle::SameLine) => self.text(" "),
+            (Some(_), BraceStyle::NextLine) => self.newline(),
+            (None, _) => self.list([]),
+        };
+
+        // a unit without a body is an `extern` declaration, e.g., of a
+        // Verilog blackbox
+        self.list([
+            self.build_unit_head(
+                &unit.head,
+                unit.body.is_none(),
+                header_body_separator,
+            ),
+            match &unit.body {
+                Some(body) => self.build_expression(body),
+                None => self.text(";"),
+            },
+        ])
+    }
+
+    /// Builds everything in `head` up to the body of the unit, ending with
+    /// `header_body_separator` unless a where clause has to break. If
+    /// `is_extern`, the unit kind is preceded by `extern`.
+    pub fn build_unit_head(
+        &self,
+        head: &ast::UnitHead,
+        is_extern: bool,
+        header_body_separator: DocumentIdx,
+    ) -> DocumentIdx {
         let mut list = vec![];
 
-        list.push(self.build_attribute_list(&unit.head.attributes, true));
+        list.push(self.build_attribute_list(&head.attributes, true));
 
-        list.push(match &*unit.head.unit_kind {
-            ast::UnitKind::Function => self.text("fn"),
-            ast::UnitKind::Entity => self.text("entity"),
-            ast::UnitKind::Pipeline(depth) => self.list([
-                self.text("pipeline("),
-                self.build_type_expression(depth),
-                self.text(")"),
-            ]),
-        });
+        if is_extern {
+            list.push(self.text("extern "));
+        }
+
+        list.push(self.build_unit_kind(&head.unit_kind));
 
-        list.push(self.text(format!(" {}", unit.head.name)));
+        list.push(self.text(format!(" {}", head.name)));
 
-        if let Some(type_params) = &unit.head.type_params {
+        if let Some(type_params) = &head.type_params {
             list.push(self.group(
                 lexer::TokenKind::Lt.as_str(),
                 &type_params.inner,
@@ -210,17 +762,32 @@ impl<'code> DocumentBuilder<'code> {
             ));
         }
 
-        let parameter_list_doc = self.build_parameter_list(&unit.head.inputs);
+        let parameter_list_doc = self.build_parameter_list(&head.inputs);
         let parameter_open = self.token(lexer::TokenKind::OpenParen);
         let parameter_close = self.token(lexer::TokenKind::CloseParen);
 
-        let output_type_doc = if let Some((_, output_type)) =
-            &unit.head.output_type
-        {
-            self.list([self.text(" -> "), self.build_type_spec(output_type)])
-        } else {
-            self.list([])
-        };
+        let (output_type_doc, tall_output_type_doc) =
+            if let Some((_, output_type)) = &head.output_type {
+                let output_type = self.build_type_spec(output_type);
+                let attached = self.list([
+                    self.text(format!(" {RETURN_TYPE_ARROW} ")),
+                    output_type,
+                ]);
+                let tall = match self.config.return_type_break {
+                    ReturnTypeBreak::Attached => attached,
+                    ReturnTypeBreak::OwnLine => self.nest(
+                        self.list([
+                            self.newline(),
+                            self.text(format!("{RETURN_TYPE_ARROW} ")),
+                            output_type,
+                        ]),
+                        self.indent,
+                    ),
+                };
+                (attached, tall)
+            } else {
+                (self.list([]), self.list([]))
+            };
 
         list.push(self.try_catch(
             self.list([
@@ -240,23 +807,25 @@ impl<'code> DocumentBuilder<'code> {
                     parameter_open,
                     parameter_list_doc.1,
                     parameter_close,
-                    output_type_doc,
+                    tall_output_type_doc,
                 ]),
             ),
         ));
 
-        if !unit.head.where_clauses.is_empty() {
-            todo!()
+        if head.where_clauses.is_empty() {
+            list.push(header_body_separator);
+        } else {
+            let where_doc = self.build_where_clauses(&head.where_clauses);
+            list.push(self.try_catch(
+                self.list([where_doc.0, header_body_separator]),
+                where_doc.1,
+            ));
         }
 
-        list.push(match &unit.body {
-            Some(body) => {
-                self.list([self.text(" "), self.build_expression(body)])
-            }
-            None => self.text(";"),
-        });
-
-        self.list(list)
+        self.with_width(
+            self.config.construct_max_width.signature,
+            self.list(list),
+        )
     }
 
     pub fn build_type_declaration(
@@ -330,6 +899,11 @@ impl<'code> DocumentBuilder<'code> {
     ) -> DocumentIdx {
         let mut list = vec![self.text(variant.name.to_string())];
         if let Some(parameter_list) = &variant.args {
+            if parameter_list.self_.is_none() && parameter_list.args.is_empty()
+            {
+                list.push(self.text(" {}"));
+                return self.list(list);
+            }
             let parameter_list_doc = self.build_parameter_list(parameter_list);
             list.extend([
                 self.text(" {"),
@@ -348,10 +922,16 @@ impl<'code> DocumentBuilder<'code> {
     }
 
     pub fn build_module(&self, item: &Loc<ast::Module>) -> DocumentIdx {
+        if item.body.members.is_empty() {
+            return self.text(format!("mod {} {{}}", item.name));
+        }
         self.list([
             self.text(format!("mod {} {{", item.name)),
             self.newline(),
-            self.nest(self.build_module_body(&item.body), self.indent),
+            self.nest(
+                self.build_module_body(&item.body, item.line_index(self)),
+                self.indent,
+            ),
             self.newline(),
             self.text("}"),
         ])
@@ -360,21 +940,9 @@ impl<'code> DocumentBuilder<'code> {
     pub fn build_module_body(
         &self,
         body: &Loc<ast::ModuleBody>,
+        first_line_index: usize,
     ) -> DocumentIdx {
-        let mut list = vec![];
-        let mut last_line_index = 0;
-        for (i, item) in body.members.iter().enumerate() {
-            let item_line_index = span_of_item(item).line_index(self);
-            if i > 0 {
-                if last_line_index < item_line_index - 1 {
-                    list.push(self.newline());
-                }
-                list.push(self.newline());
-            }
-            list.push(self.build_item(item));
-            last_line_index = item_line_index;
-        }
-        self.list(list)
+        self.build_items(&body.members, first_line_index)
     }
 
     pub fn build_use(
@@ -383,14 +951,47 @@ impl<'code> DocumentBuilder<'code> {
     ) -> DocumentIdx {
         let ast::UseStatement { path, alias } = &use_statement.inner;
 
-        let mut line = vec![self.text("use "), self.build_path(path)];
-
+        let mut tail = vec![];
         if let Some(alias) = alias {
-            line.push(self.text(format!(" as {alias}")));
+            tail.push(self.text(format!(" as {alias}")));
         }
+        tail.push(self.text(";"));
+        let tail = self.list(tail);
+
+        let segments = path
+            .inner
+            .0
+            .iter()
+            .map(|component| component.to_string())
+            .collect::<Vec<_>>();
+        let path_doc = match segments.split_first() {
+            // a path too long for one line is broken after every `::`
+            Some((first, rest)) if !rest.is_empty() => {
+                let mut broken_rest = vec![];
+                for (index, segment) in rest.iter().enumerate() {
+                    let separator =
+                        if index + 1 < rest.len() { "::" } else { "" };
+                    broken_rest.extend([
+                        self.newline(),
+                        self.text(format!("{segment}{separator}")),
+                    ]);
+                }
+                self.try_catch(
+                    self.list([self.build_path(path), tail]),
+                    self.list([
+                        self.text(format!("{first}::")),
+                        self.nest(self.list(broken_rest), self.indent),
+                        tail,
+                    ]),
+                )
+            }
+            _ => self.list([self.build_path(path), tail]),
+        };
 
-        line.push(self.text(";"));
-        self.list(line)
+        self.with_width(
+            self.config.construct_max_width.use_statement,
+            self.list([self.text("use "), path_doc]),
+        )
     }
 
     pub fn build_impl_block(
@@ -415,11 +1016,18 @@ impl<'code> DocumentBuilder<'code> {
         }
         list.push(self.build_type_spec(&impl_block.target));
 
-        if !impl_block.where_clauses.is_empty() {
-            todo!()
+        if impl_block.where_clauses.is_empty() {
+            list.push(self.body_open_brace());
+        } else {
+            let where_doc = self.build_where_clauses(&impl_block.where_clauses);
+            list.push(self.try_catch(
+                self.list([where_doc.0, self.body_open_brace()]),
+                self.list([
+                    where_doc.1,
+                    self.token(lexer::TokenKind::OpenBrace),
+                ]),
+            ));
         }
-
-        list.push(self.text(" {"));
         if !impl_block.units.is_empty() {
             list.push(self.newline());
             let mut unit_list = vec![];
@@ -437,6 +1045,61 @@ impl<'code> DocumentBuilder<'code> {
         self.list(list)
     }
 
+    pub fn build_trait_definition(
+        &self,
+        trait_definition: &Loc<ast::TraitDef>,
+    ) -> DocumentIdx {
+        let mut list =
+            vec![self.text(format!("trait {}", trait_definition.name))];
+        if let Some(type_params) = &trait_definition.type_params {
+            list.push(self.group(
+                lexer::TokenKind::Lt.as_str(),
+                &type_params.inner,
+                lexer::TokenKind::Comma,
+                lexer::TokenKind::Gt.as_str(),
+            ));
+        }
+
+        if trait_definition.methods.is_empty()
+            && trait_definition.where_clauses.is_empty()
+        {
+            list.push(self.text(" {}"));
+            return self.list(list);
+        }
+
+        if trait_definition.where_clauses.is_empty() {
+            list.push(self.body_open_brace());
+        } else {
+            let where_doc =
+                self.build_where_clauses(&trait_definition.where_clauses);
+            list.push(self.try_catch(
+                self.list([where_doc.0, self.body_open_brace()]),
+                self.list([
+                    where_doc.1,
+                    self.token(lexer::TokenKind::OpenBrace),
+                ]),
+            ));
+        }
+        if !trait_definition.methods.is_empty() {
+            list.push(self.newline());
+            let mut method_list = vec![];
+            for (i, method) in trait_definition.methods.iter().enumerate() {
+                if i > 0 {
+                    method_list.push(self.newline());
+                }
+                method_list.extend([
+                    self.build_unit_head(method, false, self.list([])),
+                    self.text(";"),
+                ]);
+            }
+            list.push(self.nest(self.list(method_list), self.indent));
+            list.push(self.newline());
+        }
+        list.push(self.text("}"));
+
+        self.list(list)
+    }
+
     pub fn build_path(&self, path: &Loc<Path>) -> DocumentIdx {
         self.text(
             path.inner
@@ -448,59 +1111,206 @@ impl<'code> DocumentBuilder<'code> {
         )
     }
 
-    pub fn build_statement(
+    /// `= value` after a binding starting on `first_line_index`, preferring
+    /// to keep it on the line of the binding, then to move `value` whole onto
+    /// its own indented line, and only then to break `value` itself after
+    /// ` = `.
+    fn build_binding_value(
         &self,
-        statement: &Loc<ast::Statement>,
+        value: &Loc<ast::Expression>,
+        first_line_index: usize,
     ) -> DocumentIdx {
-        let mut list = match &**statement {
-            ast::Statement::Label(loc) => todo!(),
-            ast::Statement::Declaration(vec) => todo!(),
-            ast::Statement::Binding(binding) => {
-                let mut list = vec![
-                    self.text("let "),
-                    self.build_pattern(&binding.pattern),
-                ];
-
-                if let Some(ty) = &binding.ty {
-                    list.extend([self.text(": "), self.build_type_spec(ty)]);
-                }
+        let value_line_index = value.line_index(self);
+        let value = self.build_expression(value);
+        let same_line = self.list([self.text(" = "), value]);
+        let own_line = self.list([
+            self.text(" ="),
+            self.nest(self.list([self.newline(), value]), self.indent),
+        ]);
+
+        // in minimal mode, keep `value` on its own line if it already was
+        if self.config.minimal && value_line_index != first_line_index {
+            return self.try_catch(own_line, same_line);
+        }
+        self.try_catch(same_line, self.try_catch(own_line, same_line))
+    }
 
-                list.push(self.text(" = "));
-                list.push(self.build_expression(&binding.value));
+    pub fn build_identifier(
+        &self,
+        identifier: &Loc<Identifier>,
+    ) -> DocumentIdx {
+        self.text(identifier.to_string())
+    }
 
-                list
-            }
-            ast::Statement::PipelineRegMarker(loc, loc1) => {
-                todo!()
+    /// `decl` followed by `names`, preferring them all on one line and
+    /// otherwise each on its own indented line.
+    fn build_declaration(&self, names: &[Loc<Identifier>]) -> DocumentIdx {
+        let mut name_list = vec![];
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 {
+                name_list.extend([
+                    self.token(lexer::TokenKind::Comma),
+                    self.newline(),
+                ]);
             }
-            ast::Statement::Register(register) => {
-                let mut list = vec![
+            name_list.push(self.build_identifier(name));
+        }
+        let names_idx = self.list(name_list);
+        let flat = self.list([self.text("decl "), self.flatten(names_idx)]);
+        let broken = self.list([
+            self.text("decl"),
+            self.nest(self.list([self.newline(), names_idx]), self.indent),
+        ]);
+
+        // in minimal mode, never join lines the user already broke
+        let is_broken =
+            names
+                .first()
+                .zip(names.last())
+                .is_some_and(|(first, last)| {
+                    first.line_index(self) != last.line_index(self)
+                });
+        if self.config.minimal && is_broken {
+            return broken;
+        }
+        self.try_catch(flat, broken)
+    }
+
+    /// The keyword introducing a unit of kind `unit_kind`, e.g.,
+    /// `pipeline(2)`.
+    pub fn build_unit_kind(&self, unit_kind: &ast::UnitKind) -> DocumentIdx {
+        match unit_kind {
+            ast::UnitKind::Function => self.text("fn"),
+            ast::UnitKind::Entity => self.text("entity"),
+            ast::UnitKind::Pipeline(depth) => self.list([
+                self.text("pipeline("),
+                self.build_type_expression(depth),
+                self.text(")"),
+            ]),
+        }
+    }
+
+    pub fn build_statement(
+        &self,
+        statement: &Loc<ast::Statement>,
+    ) -> DocumentIdx {
+        if self.is_skipped(statement.line_index(self)) {
+            let skipped = self.build_skipped(statement.span);
+            // the span of a statement may stop short of its semicolon
+            return if self.source_text(statement.span).ends_with(';') {
+                skipped
+            } else {
+                self.list([skipped, self.text(";")])
+            };
+        }
+        if let Some(idx) =
+            self.run_hooks(|hook| hook.build_statement(self, statement))
+        {
+            return self.spanned(statement.span, idx);
+        }
+
+        let mut list = match &**statement {
+            // labels take no semicolon, and the statements around them
+            // already keep them on a line of their own
+            ast::Statement::Label(name) => {
+                return self.spanned(
+                    statement.span,
+                    self.list([self.text("'"), self.build_identifier(name)]),
+                );
+            }
+            ast::Statement::Declaration(names) => {
+                vec![self.build_declaration(names)]
+            }
+            ast::Statement::Binding(binding) => {
+                let mut list = vec![
+                    self.text("let "),
+                    self.build_pattern(&binding.pattern),
+                ];
+
+                if let Some(ty) = &binding.ty {
+                    list.extend([self.text(": "), self.build_type_spec(ty)]);
+                }
+
+                list.push(self.build_binding_value(
+                    &binding.value,
+                    binding.pattern.line_index(self),
+                ));
+
+                list
+            }
+            ast::Statement::PipelineRegMarker(count, condition) => {
+                let mut list = vec![self.text("reg")];
+
+                if let Some(condition) = condition {
+                    list.extend([
+                        self.token(lexer::TokenKind::OpenBracket),
+                        self.build_expression(condition),
+                        self.token(lexer::TokenKind::CloseBracket),
+                    ]);
+                }
+
+                if let Some(count) = count {
+                    list.extend([
+                        self.text(" * "),
+                        self.build_type_expression(count),
+                    ]);
+                }
+
+                list
+            }
+            ast::Statement::Register(register) => {
+                let mut list = vec![
+                    self.build_attribute_list(&register.attributes, true),
                     self.text("reg("),
                     self.build_expression(&register.clock),
                     self.text(") "),
                     self.build_pattern(&register.pattern),
-                    self.text(" "),
                 ];
 
-                if !register.attributes.0.is_empty()
-                    || register.value_type.is_some()
-                    || register.initial.is_some()
-                {
-                    todo!()
+                if let Some(value_type) = &register.value_type {
+                    list.extend([
+                        self.text(": "),
+                        self.build_type_spec(value_type),
+                    ]);
                 }
 
+                let mut clauses = vec![];
                 if let Some(reset) = &register.reset {
-                    list.extend([
+                    clauses.push(self.list([
                         self.text("reset("),
                         self.build_expression(&reset.0),
                         self.text(": "),
                         self.build_expression(&reset.1),
-                        self.text(") "),
-                    ]);
+                        self.text(")"),
+                    ]));
+                }
+                if let Some(initial) = &register.initial {
+                    clauses.push(self.list([
+                        self.text("initial("),
+                        self.build_expression(initial),
+                        self.text(")"),
+                    ]));
+                }
+
+                // the `reset(...)` and `initial(...)` clauses follow the
+                // pattern on one line, or each go on their own indented line
+                if !clauses.is_empty() {
+                    let flat = clauses
+                        .iter()
+                        .flat_map(|clause| [self.text(" "), *clause])
+                        .collect::<Vec<_>>();
+                    let broken = clauses
+                        .iter()
+                        .flat_map(|clause| [self.newline(), *clause])
+                        .collect::<Vec<_>>();
+                    list.push(self.try_catch(
+                        self.list(flat),
+                        self.nest(self.list(broken), self.indent),
+                    ));
                 }
 
                 list.extend([
-                    self.text("= "),
+                    self.text(" = "),
                     self.build_expression(&register.value),
                 ]);
 
@@ -512,21 +1322,31 @@ impl<'code> DocumentBuilder<'code> {
                 self.text(" = "),
                 self.build_expression(value),
             ],
-            ast::Statement::Assert(loc) => todo!(),
-            ast::Statement::Expression(loc) => todo!(),
+            ast::Statement::Assert(condition) => {
+                vec![self.text("assert "), self.build_expression(condition)]
+            }
+            ast::Statement::Expression(expression) => {
+                vec![self.build_expression(expression)]
+            }
         };
         list.push(self.text(";"));
-        self.list(list)
+        self.spanned(statement.span, self.list(list))
     }
 
     pub fn build_expression(
         &self,
         expression: &Loc<ast::Expression>,
     ) -> DocumentIdx {
-        match &**expression {
+        if let Some(idx) =
+            self.run_hooks(|hook| hook.build_expression(self, expression))
+        {
+            return self.spanned(expression.span, idx);
+        }
+
+        let idx = match &**expression {
             ast::Expression::Identifier(path) => self.build_path(path),
-            ast::Expression::IntLiteral(int_literal) => {
-                self.text(int_literal.to_string())
+            ast::Expression::IntLiteral(_) => {
+                self.build_int_literal(expression.span)
             }
             ast::Expression::BoolLiteral(bool_literal) => {
                 self.text(bool_literal.to_string())
@@ -538,27 +1358,42 @@ impl<'code> DocumentBuilder<'code> {
                     ast::BitLiteral::HighImp => "UNDEF",
                 })
             }
-            ast::Expression::ArrayLiteral(array_literal) => self.group(
-                lexer::TokenKind::OpenBracket.as_str(),
-                array_literal,
-                lexer::TokenKind::Comma,
-                lexer::TokenKind::CloseBracket.as_str(),
-            ),
-            ast::Expression::ArrayShorthandLiteral(loc, loc1) => todo!(),
-            ast::Expression::Index(loc, loc1) => todo!(),
-            ast::Expression::RangeIndex { target, start, end } => todo!(),
+            ast::Expression::ArrayLiteral(array_literal) => {
+                self.build_array_literal(array_literal)
+            }
+            ast::Expression::ArrayShorthandLiteral(value, count) => self
+                .build_brackets(self.list([
+                    self.build_expression(value),
+                    self.text("; "),
+                    self.build_expression(count),
+                ])),
+            ast::Expression::Index(target, index) => self.list([
+                self.build_postfix_target(target),
+                self.build_brackets(self.build_expression(index)),
+            ]),
+            ast::Expression::RangeIndex { target, start, end } => self.list([
+                self.build_postfix_target(target),
+                self.build_brackets(self.list([
+                    self.build_expression(start),
+                    self.text(".."),
+                    self.build_expression(end),
+                ])),
+            ]),
             ast::Expression::TupleLiteral(items) => self.group(
                 lexer::TokenKind::OpenParen.as_str(),
                 items,
                 lexer::TokenKind::Comma,
                 lexer::TokenKind::CloseParen.as_str(),
             ),
-            ast::Expression::TupleIndex(loc, loc1) => todo!(),
+            ast::Expression::TupleIndex(target, index) => self.list([
+                self.build_postfix_target(target),
+                self.text(format!("#{index}")),
+            ]),
             ast::Expression::FieldAccess(parent, field) => self.list([
                 self.build_expression(parent),
                 self.text(format!(".{field}")),
             ]),
-            ast::Expression::CreatePorts => todo!(),
+            ast::Expression::CreatePorts => self.text("port"),
             ast::Expression::Call {
                 kind,
                 callee,
@@ -614,20 +1449,13 @@ impl<'code> DocumentBuilder<'code> {
 
                 self.list(list)
             }
-            ast::Expression::If(condition, true_branch, false_branch) => self
-                .list([
-                    self.text("if "),
-                    self.build_expression(condition),
-                    self.text(" "),
-                    self.build_expression(true_branch),
-                    self.text(" else "),
-                    self.build_expression(false_branch),
-                ]),
+            ast::Expression::If(..) => self.build_if_chain(expression),
             ast::Expression::Match(against, arms) => {
                 let mut list =
                     vec![self.text("match "), self.build_expression(against)];
                 if !arms.is_empty() {
                     let mut arm_list = vec![];
+                    let mut any_multiline_arm = false;
                     for arm in &arms.inner {
                         let pattern = self.build_pattern(&arm.0);
                         let case = self.list([
@@ -637,7 +1465,15 @@ impl<'code> DocumentBuilder<'code> {
                             )),
                             self.build_expression(&arm.1),
                         ]);
-                        arm_list.push(
+                        let arm_doc = if is_multiline_block(&arm.1) {
+                            // the `=>` and the block stay attached to the
+                            // pattern, but the block itself is never flattened
+                            any_multiline_arm = true;
+                            self.try_catch(
+                                self.list([self.flatten(pattern), case]),
+                                self.list([pattern, case]),
+                            )
+                        } else {
                             self.try_catch(
                                 self.list([
                                     self.flatten(pattern),
@@ -648,80 +1484,67 @@ impl<'code> DocumentBuilder<'code> {
                                     self.list([pattern, case]),
                                 ),
                             )
-                            .at_loc(&arm.0),
-                        );
+                        };
+                        arm_list.push(arm_doc.at_loc(&arm.0));
                     }
 
                     let arms_doc =
                         self.group_raw(&arm_list, lexer::TokenKind::Comma);
-                    list.extend([
-                        self.text(" {"),
+                    let broken_arms_doc = self.list([
+                        self.body_open_brace(),
+                        arms_doc.1,
+                        self.text("}"),
+                    ]);
+                    list.push(if any_multiline_arm {
+                        broken_arms_doc
+                    } else {
                         self.try_catch(
                             self.list([
-                                self.text(" "),
+                                self.text(" { "),
                                 arms_doc.0,
-                                self.text(" "),
+                                self.text(" }"),
                             ]),
-                            arms_doc.1,
-                        ),
-                        self.text("}"),
-                    ]);
+                            broken_arms_doc,
+                        )
+                    });
+                } else {
+                    list.push(self.text(" {}"));
                 }
                 self.list(list)
             }
-            // TODO: proper parenthesization in both of these
             ast::Expression::UnaryOperator(unary_operator, inner) => {
                 self.list([
                     self.text(unary_operator.to_string()),
-                    self.build_expression(inner),
+                    self.build_operand(inner, u8::MAX),
                 ])
             }
-            ast::Expression::BinaryOperator(left, op, right) => self.list([
-                self.build_expression(left),
-                self.text(format!(" {op} ")),
-                self.build_expression(right),
-            ]),
+            ast::Expression::BinaryOperator(..) => {
+                self.build_binary_operator_chain(expression)
+            }
             ast::Expression::Block(block) => {
-                let mut list = vec![self.token(lexer::TokenKind::OpenBrace)];
-                if block.statements.len()
-                    + block.result.as_ref().map_or(0, |_| 1)
-                    > 0
-                {
-                    list.push(self.newline());
-
-                    let mut nest = vec![];
-
-                    let mut last_line_index = 0;
-                    for (i, statement) in block.statements.iter().enumerate() {
-                        let item_line_index = statement.line_index(self);
-                        if i > 0 && last_line_index < item_line_index - 1 {
-                            nest.push(self.newline());
+                self.build_block(block, expression.line_index(self))
+            }
+            ast::Expression::PipelineReference { stage, name, .. } => self
+                .list([
+                    self.text("stage("),
+                    match stage {
+                        ast::PipelineStageReference::Relative(offset) => {
+                            self.build_type_expression(offset)
                         }
-                        nest.push(self.build_statement(statement));
-                        nest.push(self.newline());
-                        last_line_index = item_line_index;
-                    }
-
-                    if let Some(result) = &block.result {
-                        nest.push(self.build_expression(result));
-                        nest.push(self.newline());
-                    }
-
-                    list.push(self.nest(self.list(nest), self.indent));
-                }
-                list.push(self.token(lexer::TokenKind::CloseBrace));
-
-                self.list(list)
+                        ast::PipelineStageReference::Absolute(label) => {
+                            self.text(label.to_string())
+                        }
+                    },
+                    self.text(format!(").{name}")),
+                ]),
+            ast::Expression::TypeLevelIf(..) => self.build_if_chain(expression),
+            ast::Expression::StageValid => self.text("stage.valid"),
+            ast::Expression::StageReady => self.text("stage.ready"),
+            // copied as written so that escapes are kept as they are
+            ast::Expression::StrLiteral(_)
+            | ast::Expression::StaticUnreachable(_) => {
+                self.text(self.source_text(expression.span))
             }
-            ast::Expression::PipelineReference {
-                stage_kw_and_reference_loc,
-                stage,
-                name,
-            } => todo!(),
-            ast::Expression::TypeLevelIf(loc, loc1, loc2) => todo!(),
-            ast::Expression::StageValid => todo!(),
-            ast::Expression::StageReady => todo!(),
-            ast::Expression::StrLiteral(loc) => todo!(),
             ast::Expression::Parenthesized(inner) => self.list([
                 self.token(lexer::TokenKind::OpenParen),
                 self.build_expression(inner),
@@ -731,10 +1554,359 @@ impl<'code> DocumentBuilder<'code> {
                 unit_kind,
                 args,
                 body,
-            } => todo!(),
-            ast::Expression::Unsafe(loc) => todo!(),
-            ast::Expression::StaticUnreachable(loc) => todo!(),
+            } => self.list([
+                self.build_unit_kind(unit_kind),
+                self.text(" "),
+                self.group("|", &args.inner, lexer::TokenKind::Comma, "|"),
+                self.text(" "),
+                self.build_block_flattening(body),
+            ]),
+            ast::Expression::Unsafe(block) => self.list([
+                self.text("unsafe "),
+                self.build_block_flattening(block),
+            ]),
+        };
+        self.spanned(expression.span, idx)
+    }
+
+    /// Builds a chain of binary operators of the same precedence, e.g., `a +
+    /// b - c`, either on one line or with each operator leading an indented
+    /// continuation line.
+    pub fn build_binary_operator_chain(
+        &self,
+        expression: &Loc<ast::Expression>,
+    ) -> DocumentIdx {
+        let chain_precedence = match &**expression {
+            ast::Expression::BinaryOperator(_, op, _) => {
+                binary_operator_precedence(&op.to_string())
+            }
+            _ => return self.build_expression(expression),
+        };
+
+        // binary operators are left-associative, so the chain extends down
+        // the left operands
+        let mut rest = vec![];
+        let mut first = expression;
+        while let ast::Expression::BinaryOperator(left, op, right) = &**first {
+            let op = op.to_string();
+            if binary_operator_precedence(&op) != chain_precedence {
+                break;
+            }
+            rest.push((op, right));
+            first = &**left;
         }
+        rest.reverse();
+
+        let first = self.build_operand(first, chain_precedence);
+        let rest = rest
+            .into_iter()
+            .map(|(op, operand)| {
+                (op, self.build_operand(operand, chain_precedence + 1))
+            })
+            .collect::<Vec<_>>();
+
+        let mut flat_list = vec![first];
+        let mut broken_list = vec![];
+        for (op, operand) in rest {
+            flat_list.extend([self.text(format!(" {op} ")), operand]);
+            broken_list.extend([
+                self.newline(),
+                self.text(format!("{op} ")),
+                operand,
+            ]);
+        }
+
+        self.try_catch(
+            self.list(flat_list),
+            self.list([first, self.nest(self.list(broken_list), self.indent)]),
+        )
+    }
+
+    /// Builds `operand`, wrapping it in parentheses if it is a binary operator
+    /// binding less tightly than `min_precedence`.
+    fn build_operand(
+        &self,
+        operand: &Loc<ast::Expression>,
+        min_precedence: u8,
+    ) -> DocumentIdx {
+        let needs_parentheses = match &**operand {
+            ast::Expression::BinaryOperator(_, op, _) => {
+                binary_operator_precedence(&op.to_string()) < min_precedence
+            }
+            _ => false,
+        };
+        if needs_parentheses {
+            self.list([
+                self.token(lexer::TokenKind::OpenParen),
+                self.build_expression(operand),
+                self.token(lexer::TokenKind::CloseParen),
+            ])
+        } else {
+            self.build_expression(operand)
+        }
+    }
+
+    /// Builds an `if` or `gen if` expression together with any `else if`s or
+    /// `else gen if`s following it, either entirely on one line or with each
+    /// branch broken, so that an `else if` chain reads as a flat sequence
+    /// rather than nesting.
+    pub fn build_if_chain(
+        &self,
+        expression: &Loc<ast::Expression>,
+    ) -> DocumentIdx {
+        let mut branches = vec![];
+        let mut rest = expression;
+        loop {
+            let (keyword, condition, true_branch, false_branch) = match &**rest
+            {
+                ast::Expression::If(condition, true_branch, false_branch) => (
+                    "if",
+                    self.build_expression(condition),
+                    true_branch,
+                    false_branch,
+                ),
+                ast::Expression::TypeLevelIf(
+                    condition,
+                    true_branch,
+                    false_branch,
+                ) => (
+                    "gen if",
+                    self.build_expression(condition),
+                    true_branch,
+                    false_branch,
+                ),
+                _ => break,
+            };
+            branches.push((keyword, condition, true_branch));
+            rest = false_branch;
+        }
+        let else_branch = rest;
+
+        let never_flatten = is_multiline_block(else_branch)
+            || branches
+                .iter()
+                .any(|(_, _, branch)| is_multiline_block(branch));
+
+        let mut list = vec![];
+        for (i, (keyword, condition, branch)) in
+            branches.into_iter().enumerate()
+        {
+            if i > 0 {
+                list.push(self.text(" else "));
+            }
+            // a condition that does not fit goes on its own indented line
+            // between the `if` and the branch
+            list.push(self.try_catch(
+                self.list([
+                    self.text(format!("{keyword} ")),
+                    condition,
+                    self.text(" "),
+                ]),
+                self.list([
+                    self.text(keyword),
+                    self.nest(
+                        self.list([self.newline(), condition]),
+                        self.indent,
+                    ),
+                    self.newline(),
+                ]),
+            ));
+            list.push(self.build_expression(branch));
+        }
+        list.extend([self.text(" else "), self.build_expression(else_branch)]);
+
+        let chain = self.list(list);
+        if never_flatten {
+            chain
+        } else {
+            self.try_catch(self.flatten(chain), chain)
+        }
+    }
+
+    /// Builds the statements and result of `block` between braces, one to a
+    /// line. `first_line_index` is the line of the opening brace.
+    pub fn build_block(
+        &self,
+        block: &ast::Block,
+        first_line_index: usize,
+    ) -> DocumentIdx {
+        let mut list = vec![self.token(lexer::TokenKind::OpenBrace)];
+        if block.statements.len() + block.result.as_ref().map_or(0, |_| 1) > 0 {
+            list.push(self.newline());
+
+            let mut nest = vec![];
+
+            // the line the last statement ended on, and whether it was
+            // a stage boundary
+            let mut last_end = None::<(usize, bool)>;
+            let mut first_line_index = first_line_index;
+            let mut statements = block.statements.as_slice();
+            while let Some(statement) = statements.first() {
+                let region = self.build_format_off_region(
+                    statements,
+                    |statement| statement.span,
+                    first_line_index,
+                );
+                let start_line_index = region.as_ref().map_or_else(
+                    || statement.line_index(self),
+                    |region| region.start_line_index,
+                );
+
+                // a stage boundary is never followed by a blank line
+                if let Some((last_end_line_index, false)) = last_end {
+                    nest.extend(
+                        self.blank_lines(last_end_line_index, start_line_index),
+                    );
+                }
+                let (idx, length, end_line_index, is_boundary) = match region {
+                    Some(region) => (
+                        region.idx,
+                        region.length,
+                        region.end_line_index,
+                        false,
+                    ),
+                    None => (
+                        self.build_statement(statement),
+                        1,
+                        statement.end_line_index(self),
+                        is_stage_boundary(statement),
+                    ),
+                };
+                nest.push(idx);
+                nest.push(self.newline());
+                statements = &statements[length..];
+                last_end = Some((end_line_index, is_boundary));
+                first_line_index = end_line_index + 1;
+            }
+
+            if let Some(result) = &block.result {
+                if let Some((last_end_line_index, _)) = last_end {
+                    nest.extend(self.blank_lines(
+                        last_end_line_index,
+                        result.line_index(self),
+                    ));
+                }
+                nest.push(self.build_expression(result));
+                nest.push(self.newline());
+            }
+
+            list.push(self.nest(self.list(nest), self.indent));
+        }
+        list.push(self.token(lexer::TokenKind::CloseBrace));
+
+        self.list(list)
+    }
+
+    /// Builds `block` like [`Self::build_block`], on one line if it has no
+    /// statements and fits.
+    fn build_block_flattening(&self, block: &Loc<ast::Block>) -> DocumentIdx {
+        let block_idx = self.build_block(block, block.line_index(self));
+        if block.statements.is_empty() {
+            self.try_catch(self.flatten(block_idx), block_idx)
+        } else {
+            block_idx
+        }
+    }
+
+    /// Builds the target of a postfix operator such as indexing, wrapping it
+    /// in parentheses if it is a unary or binary operator.
+    fn build_postfix_target(
+        &self,
+        target: &Loc<ast::Expression>,
+    ) -> DocumentIdx {
+        match &**target {
+            ast::Expression::UnaryOperator(..)
+            | ast::Expression::BinaryOperator(..) => self.list([
+                self.token(lexer::TokenKind::OpenParen),
+                self.build_expression(target),
+                self.token(lexer::TokenKind::CloseParen),
+            ]),
+            _ => self.build_expression(target),
+        }
+    }
+
+    /// Builds an array literal on one line if it fits, and otherwise broken
+    /// according to the configured [`ArrayLayout`].
+    pub fn build_array_literal(
+        &self,
+        elements: &[Loc<ast::Expression>],
+    ) -> DocumentIdx {
+        let use_fill = match self.config.array_layout {
+            ArrayLayout::Vertical => false,
+            ArrayLayout::Fill => true,
+            ArrayLayout::Auto => {
+                elements.iter().all(|element| is_table_cell(element))
+            }
+        };
+        // in minimal mode, lines the user broke are kept as they are
+        if !use_fill || elements.is_empty() || self.config.minimal {
+            return self.group(
+                lexer::TokenKind::OpenBracket.as_str(),
+                elements,
+                lexer::TokenKind::Comma,
+                lexer::TokenKind::CloseBracket.as_str(),
+            );
+        }
+
+        let cells = elements
+            .iter()
+            .map(|element| self.build_expression(element))
+            .collect::<Vec<_>>();
+        let cell_width = cells
+            .iter()
+            .map(|cell| self.flat_width(*cell))
+            .max()
+            .unwrap_or(0);
+
+        let mut entries = vec![];
+        for (i, cell) in cells.iter().enumerate() {
+            let mut entry = vec![
+                self.text(" ".repeat(cell_width - self.flat_width(*cell))),
+                *cell,
+            ];
+            if i + 1 < cells.len()
+                || self.config.trailing_comma != TrailingComma::Never
+            {
+                entry.push(self.token(lexer::TokenKind::Comma));
+            }
+            entries.push(self.list(entry));
+        }
+
+        let (flat_idx, _) = self.group_raw(elements, lexer::TokenKind::Comma);
+        self.try_catch(
+            self.list([
+                self.token(lexer::TokenKind::OpenBracket),
+                flat_idx,
+                self.token(lexer::TokenKind::CloseBracket),
+            ]),
+            self.list([
+                self.token(lexer::TokenKind::OpenBracket),
+                self.nest(
+                    self.list([self.newline(), self.fill(entries)]),
+                    self.indent,
+                ),
+                self.newline(),
+                self.token(lexer::TokenKind::CloseBracket),
+            ]),
+        )
+    }
+
+    /// Builds `[contents]`, moving `contents` onto its own indented line when
+    /// it does not fit.
+    fn build_brackets(&self, contents: DocumentIdx) -> DocumentIdx {
+        self.try_catch(
+            self.list([
+                self.token(lexer::TokenKind::OpenBracket),
+                contents,
+                self.token(lexer::TokenKind::CloseBracket),
+            ]),
+            self.list([
+                self.token(lexer::TokenKind::OpenBracket),
+                self.nest(self.list([self.newline(), contents]), self.indent),
+                self.newline(),
+                self.token(lexer::TokenKind::CloseBracket),
+            ]),
+        )
     }
 
     pub fn build_turbofish(
@@ -742,15 +1914,11 @@ impl<'code> DocumentBuilder<'code> {
         turbofish: &Loc<ast::TurbofishInner>,
     ) -> DocumentIdx {
         match &**turbofish {
-            ast::TurbofishInner::Named(vec) => todo!(),
+            ast::TurbofishInner::Named(arguments) => self
+                .list([self.text("::"), self.generic_group("$<", arguments)]),
             ast::TurbofishInner::Positional(arguments) => self.list([
                 self.text("::"),
-                self.group(
-                    lexer::TokenKind::Lt.as_str(),
-                    arguments,
-                    lexer::TokenKind::Comma,
-                    lexer::TokenKind::Gt.as_str(),
-                ),
+                self.generic_group(lexer::TokenKind::Lt.as_str(), arguments),
             ]),
         }
     }
@@ -775,11 +1943,34 @@ impl<'code> DocumentBuilder<'code> {
         }
     }
 
+    pub fn build_named_turbofish(
+        &self,
+        named_turbofish: &ast::NamedTurbofish,
+    ) -> DocumentIdx {
+        match named_turbofish {
+            ast::NamedTurbofish::Short(name) => self.text(name.to_string()),
+            ast::NamedTurbofish::Full(name, value) => self.list([
+                self.text(format!("{name}: ")),
+                self.build_type_expression(value),
+            ]),
+        }
+    }
+
     pub fn build_named_argument(
         &self,
         named_argument: &ast::NamedArgument,
     ) -> DocumentIdx {
         match named_argument {
+            ast::NamedArgument::Full(name, current)
+                if self.config.use_field_shorthand
+                    && matches!(
+                        &**current,
+                        ast::Expression::Identifier(path)
+                            if is_single_identifier(path, name)
+                    ) =>
+            {
+                self.text(name.to_string())
+            }
             ast::NamedArgument::Full(name, current) => self.list([
                 self.text(format!("{name}: ")),
                 self.build_expression(current),
@@ -788,11 +1979,41 @@ impl<'code> DocumentBuilder<'code> {
         }
     }
 
+    pub fn build_named_pattern_argument(
+        &self,
+        (name, pattern): &AstNamedPatternArgument,
+    ) -> DocumentIdx {
+        match pattern {
+            Some(pattern)
+                if !(self.config.use_field_shorthand
+                    && matches!(
+                        &**pattern,
+                        ast::Pattern::Path(path)
+                            if is_single_identifier(path, name)
+                    )) =>
+            {
+                self.list([
+                    self.text(format!("{name}: ")),
+                    self.build_pattern(pattern),
+                ])
+            }
+            _ => self.text(name.to_string()),
+        }
+    }
+
+    /// Builds the integer literal at `span` as written in the source, adjusted
+    /// to the configured `hex_literal_case` and `literal_separators`.
+    pub fn build_int_literal(&self, span: Span) -> DocumentIdx {
+        self.text(format_int_literal(
+            self.source_text(span),
+            self.config.hex_literal_case,
+            self.config.literal_separators.inner,
+        ))
+    }
+
     pub fn build_pattern(&self, pattern: &Loc<ast::Pattern>) -> DocumentIdx {
         match &**pattern {
-            ast::Pattern::Integer(int_literal) => {
-                self.text(int_literal.to_string())
-            }
+            ast::Pattern::Integer(_) => self.build_int_literal(pattern.span),
             ast::Pattern::Bool(bool_literal) => {
                 self.text(bool_literal.to_string())
             }
@@ -803,7 +2024,12 @@ impl<'code> DocumentBuilder<'code> {
                 lexer::TokenKind::Comma,
                 lexer::TokenKind::CloseParen.as_str(),
             ),
-            ast::Pattern::Array(vec) => todo!(),
+            ast::Pattern::Array(elements) => self.group(
+                lexer::TokenKind::OpenBracket.as_str(),
+                elements,
+                lexer::TokenKind::Comma,
+                lexer::TokenKind::CloseBracket.as_str(),
+            ),
             ast::Pattern::Type(name, argument_pattern) => self.list([
                 self.build_path(name),
                 self.build_argument_pattern(argument_pattern),
@@ -816,7 +2042,12 @@ impl<'code> DocumentBuilder<'code> {
         argument_pattern: &Loc<ast::ArgumentPattern>,
     ) -> DocumentIdx {
         match &**argument_pattern {
-            ast::ArgumentPattern::Named(vec) => todo!(),
+            ast::ArgumentPattern::Named(arguments) => self.group(
+                "$(",
+                arguments,
+                lexer::TokenKind::Comma,
+                lexer::TokenKind::CloseParen.as_str(),
+            ),
             ast::ArgumentPattern::Positional(tuple) => self.group(
                 lexer::TokenKind::OpenParen.as_str(),
                 tuple,
@@ -834,11 +2065,15 @@ impl<'code> DocumentBuilder<'code> {
             ast::TypeExpression::TypeSpec(type_spec) => {
                 self.build_type_spec(type_spec)
             }
-            ast::TypeExpression::Integer(value) => self.text(value.to_string()),
+            ast::TypeExpression::Integer(_) => {
+                self.build_int_literal(type_expression.span)
+            }
             ast::TypeExpression::ConstGeneric(expression) => {
                 self.build_expression(expression)
             }
-            ast::TypeExpression::String(string) => todo!(),
+            ast::TypeExpression::String(_) => {
+                self.text(self.source_text(type_expression.span))
+            }
         }
     }
 
@@ -863,11 +2098,9 @@ impl<'code> DocumentBuilder<'code> {
             ast::TypeSpec::Named(path, type_params) => {
                 let mut list = vec![self.build_path(path)];
                 if let Some(params) = type_params {
-                    list.push(self.group(
+                    list.push(self.generic_group(
                         lexer::TokenKind::Lt.as_str(),
                         &params.inner,
-                        lexer::TokenKind::Comma,
-                        lexer::TokenKind::Gt.as_str(),
                     ));
                 }
                 self.list(list)
@@ -889,31 +2122,9 @@ impl<'code> DocumentBuilder<'code> {
             ast::TypeParam::TypeName { name, traits } => {
                 let mut list = vec![self.text(name.to_string())];
                 if !traits.is_empty() {
-                    let mut flatten_list = vec![];
-                    let mut nest_list = vec![];
-                    for (i, trait_spec) in traits.iter().enumerate() {
-                        if i > 0 {
-                            flatten_list.push(self.text(format!(
-                                " {} ",
-                                lexer::TokenKind::Plus.as_str()
-                            )));
-                            nest_list.extend([
-                                self.newline(),
-                                self.text(format!(
-                                    "{} ",
-                                    lexer::TokenKind::Plus.as_str()
-                                )),
-                            ])
-                        }
-                        flatten_list.push(self.build_trait_spec(trait_spec));
-                        nest_list.push(self.build_trait_spec(trait_spec));
-                    }
                     list.extend([
                         self.text(": "),
-                        self.try_catch(
-                            self.flatten(self.list(flatten_list)),
-                            self.nest(self.list(nest_list), self.indent),
-                        ),
+                        self.build_trait_bounds(traits),
                     ])
                 }
                 self.list(list)
@@ -924,17 +2135,80 @@ impl<'code> DocumentBuilder<'code> {
         }
     }
 
+    /// Builds `traits` joined by `+`, breaking before each `+` if they do not
+    /// fit on one line.
+    pub fn build_trait_bounds(
+        &self,
+        traits: &[Loc<ast::TraitSpec>],
+    ) -> DocumentIdx {
+        let mut flatten_list = vec![];
+        let mut nest_list = vec![];
+        for (i, trait_spec) in traits.iter().enumerate() {
+            if i > 0 {
+                flatten_list.push(
+                    self.text(format!(" {} ", lexer::TokenKind::Plus.as_str())),
+                );
+                nest_list.extend([
+                    self.newline(),
+                    self.text(format!("{} ", lexer::TokenKind::Plus.as_str())),
+                ])
+            }
+            flatten_list.push(self.build_trait_spec(trait_spec));
+            nest_list.push(self.build_trait_spec(trait_spec));
+        }
+        self.try_catch(
+            self.flatten(self.list(flatten_list)),
+            self.nest(self.list(nest_list), self.indent),
+        )
+    }
+
+    pub fn build_where_clause(
+        &self,
+        where_clause: &ast::WhereClause,
+    ) -> DocumentIdx {
+        match where_clause {
+            ast::WhereClause::GenericInt {
+                target, expression, ..
+            } => self.list([
+                self.build_path(target),
+                self.text(": "),
+                self.token(lexer::TokenKind::OpenBrace),
+                self.build_expression(expression),
+                self.token(lexer::TokenKind::CloseBrace),
+            ]),
+            ast::WhereClause::TraitBounds { target, traits } => self.list([
+                self.build_path(target),
+                self.text(": "),
+                self.build_trait_bounds(traits),
+            ]),
+        }
+    }
+
+    /// Returns a (try, catch) pair of documents for formatting the given
+    /// `where_clauses`. The try document starts with a space and stays on the
+    /// header line, while the catch document puts `where` and each clause on
+    /// their own lines and ends with a newline.
+    pub fn build_where_clauses(
+        &self,
+        where_clauses: &[ast::WhereClause],
+    ) -> (DocumentIdx, DocumentIdx) {
+        let (try_idx, catch_idx) =
+            self.group_raw(where_clauses, lexer::TokenKind::Comma);
+        (
+            self.list([self.text(" where "), try_idx]),
+            self.list([self.newline(), self.text("where"), catch_idx]),
+        )
+    }
+
     pub fn build_trait_spec(
         &self,
         trait_spec: &Loc<ast::TraitSpec>,
     ) -> DocumentIdx {
         let mut list = vec![self.build_path(&trait_spec.path)];
         if let Some(type_params) = &trait_spec.type_params {
-            list.push(self.group(
+            list.push(self.generic_group(
                 lexer::TokenKind::Lt.as_str(),
                 &type_params.inner,
-                lexer::TokenKind::Comma,
-                lexer::TokenKind::Gt.as_str(),
             ));
         }
         self.list(list)
@@ -945,24 +2219,98 @@ impl<'code> DocumentBuilder<'code> {
         attribute: &Loc<ast::Attribute>,
     ) -> DocumentIdx {
         match &**attribute {
-            ast::Attribute::Optimize { passes } => todo!(),
-            ast::Attribute::NoMangle { all } => self.text(format!(
-                "#[no_mangle{}]",
-                if *all { "(all)" } else { "" }
-            )),
-            ast::Attribute::Fsm { state } => todo!(),
+            ast::Attribute::Optimize { passes } => self.build_attribute_call(
+                "optimize",
+                passes.iter().map(|pass| self.text(pass.to_string())),
+            ),
+            ast::Attribute::NoMangle { all } => self.build_attribute_call(
+                "no_mangle",
+                all.then(|| self.text("all")),
+            ),
+            ast::Attribute::Fsm { state } => self.build_attribute_call(
+                "fsm",
+                state.as_ref().map(|state| self.text(state.to_string())),
+            ),
             ast::Attribute::WalTraceable {
                 suffix,
                 uses_clk,
                 uses_rst,
-            } => todo!(),
-            ast::Attribute::WalTrace { clk, rst } => todo!(),
-            ast::Attribute::WalSuffix { suffix } => todo!(),
+            } => self.build_attribute_call(
+                "wal_traceable",
+                [
+                    suffix
+                        .as_ref()
+                        .map(|suffix| self.text(format!("suffix = {suffix}"))),
+                    uses_clk.then(|| self.text("uses_clk")),
+                    uses_rst.then(|| self.text("uses_rst")),
+                ]
+                .into_iter()
+                .flatten(),
+            ),
+            ast::Attribute::WalTrace { clk, rst } => self.build_attribute_call(
+                "wal_trace",
+                [("clk", clk), ("rst", rst)].into_iter().filter_map(
+                    |(name, value)| {
+                        value.as_ref().map(|value| {
+                            self.list([
+                                self.text(format!("{name} = ")),
+                                self.build_expression(value),
+                            ])
+                        })
+                    },
+                ),
+            ),
+            ast::Attribute::WalSuffix { suffix } => self.build_attribute_call(
+                "wal_suffix",
+                [self.text(suffix.to_string())],
+            ),
             ast::Attribute::Documentation { content } => {
                 self.text(format!("///{content}"))
             }
-            ast::Attribute::SurferTranslator(string) => todo!(),
+            ast::Attribute::SurferTranslator(translator) => self
+                .build_attribute_call(
+                    "surfer_translator",
+                    [self.text(format!("\"{translator}\""))],
+                ),
+        }
+    }
+
+    /// Builds `#[name(arguments)]`, breaking the arguments onto their own
+    /// lines when they do not fit, or `#[name]` if there are no arguments.
+    /// Unlike other comma groups, attribute arguments never get a trailing
+    /// comma.
+    fn build_attribute_call(
+        &self,
+        name: &str,
+        arguments: impl IntoIterator<Item = DocumentIdx>,
+    ) -> DocumentIdx {
+        let mut list = vec![];
+        for (i, argument) in arguments.into_iter().enumerate() {
+            if i > 0 {
+                list.extend([
+                    self.token(lexer::TokenKind::Comma),
+                    self.newline(),
+                ]);
+            }
+            list.push(argument);
         }
+        if list.is_empty() {
+            return self.text(format!("#[{name}]"));
+        }
+
+        let contents = self.list(list);
+        self.list([
+            self.text(format!("#[{name}(")),
+            self.try_catch(
+                self.flatten(contents),
+                self.list([
+                    self.newline(),
+                    self.nest(contents, self.indent),
+                    self.newline(),
+                ]),
+            ),
+            self.text(")]"),
+        ])
     }
 
     pub fn build_attribute_list(
@@ -970,27 +2318,69 @@ impl<'code> DocumentBuilder<'code> {
         attribute_list: &ast::AttributeList,
         always_newline: bool,
     ) -> DocumentIdx {
-        self.list(match attribute_list.0.len() {
-            0 => vec![],
-            1 => vec![
-                self.build_attribute(&attribute_list.0[0]),
-                if always_newline {
-                    self.newline()
-                } else {
-                    self.text(" ")
-                },
-            ],
-            _ => {
-                let mut list = vec![];
-                for attribute in &attribute_list.0 {
-                    list.extend([
-                        self.build_attribute(attribute),
-                        self.newline(),
-                    ]);
-                }
-                list
+        let separator = if attribute_list.0.len() == 1 && !always_newline {
+            self.text(" ")
+        } else {
+            self.newline()
+        };
+
+        let mut list = vec![];
+        let mut attributes = attribute_list.0.as_slice();
+        while let Some(attribute) = attributes.first() {
+            // a doc comment runs to the end of its line
+            let doc_comment_length = attributes
+                .iter()
+                .take_while(|attribute| {
+                    matches!(***attribute, ast::Attribute::Documentation { .. })
+                })
+                .count();
+            if doc_comment_length > 0 {
+                list.push(
+                    self.build_doc_comment(&attributes[..doc_comment_length]),
+                );
+                attributes = &attributes[doc_comment_length..];
+                continue;
             }
-        })
+            list.extend([self.build_attribute(attribute), separator]);
+            attributes = &attributes[1..];
+        }
+        self.list(list)
+    }
+
+    /// Builds a run of consecutive doc comment `lines`, each followed by a
+    /// newline, normalizing and reflowing them as configured.
+    fn build_doc_comment(&self, lines: &[Loc<ast::Attribute>]) -> DocumentIdx {
+        let contents = lines
+            .iter()
+            .filter_map(|line| match &**line {
+                ast::Attribute::Documentation { content } => {
+                    Some(content.as_str())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // the run is reflowed for the column it starts on in the source,
+        // which is where it stays once formatted
+        let width = self.config.wrap_doc_comments.then(|| {
+            let column = lines
+                .first()
+                .map_or(0, |line| self.column_at(line.span.start().to_usize()));
+            self.config
+                .comment_width
+                .unwrap_or(self.config.max_width)
+                .inner
+                .saturating_sub(column + "/// ".len())
+        });
+
+        let mut list = vec![];
+        for content in normalize_doc_comment(
+            &contents,
+            self.config.normalize_doc_comments,
+            width,
+        ) {
+            list.extend([self.text(format!("///{content}")), self.newline()]);
+        }
+        self.list(list)
     }
 
     pub fn build_parameter(&self, parameter: &AstParameter) -> DocumentIdx {
@@ -1017,6 +2407,12 @@ impl<'code> DocumentBuilder<'code> {
                 self.nest(self.text("self,"), self.indent),
             ]);
         }
+        if parameter_list.args.is_empty() {
+            if parameter_list.self_.is_some() {
+                catch_list.push(self.newline());
+            }
+            return (self.list(try_list), self.list(catch_list));
+        }
         let (try_idx, catch_idx) =
             self.group_raw(&parameter_list.args, lexer::TokenKind::Comma);
         try_list.push(try_idx);
@@ -1024,37 +2420,319 @@ impl<'code> DocumentBuilder<'code> {
         (self.list(try_list), self.list(catch_list))
     }
 
-    fn newline(&self) -> DocumentIdx {
+    /// The `{` opening a body after a header, placed according to the
+    /// configured [`BraceStyle`].
+    fn body_open_brace(&self) -> DocumentIdx {
+        match self.config.brace_style {
+            BraceStyle::SameLine => self.text(" {"),
+            BraceStyle::NextLine => self.list([
+                self.newline(),
+                self.token(lexer::TokenKind::OpenBrace),
+            ]),
+        }
+    }
+
+    fn line_index_at(&self, byte_index: usize) -> usize {
+        self.file
+            .borrow()
+            .unwrap()
+            .line_index((), byte_index)
+            .expect("span was somehow not from the file it came from")
+    }
+
+    /// The column of the byte at `byte_index` within its line.
+    fn column_at(&self, byte_index: usize) -> usize {
+        let file = self.file.borrow().unwrap();
+        file.line_range((), self.line_index_at(byte_index))
+            .map_or(0, |range| file.source()[range.start..byte_index].width())
+    }
+
+    fn set_file(&self, file: &'code SimpleFile<String, String>) {
+        self.file.replace(Some(file));
+        let format_toggles = file
+            .source()
+            .lines()
+            .enumerate()
+            .filter_map(|(line_index, line)| match line.trim() {
+                FORMAT_OFF_MARKER => Some((line_index, false)),
+                FORMAT_ON_MARKER => Some((line_index, true)),
+                _ => None,
+            })
+            .collect();
+        self.format_toggles.replace(format_toggles);
+    }
+
+    /// If formatting is turned off for a construct starting on line
+    /// `line_index`, returns the line of the [`FORMAT_OFF_MARKER`] that did
+    /// so. Markers before line `first_line_index` belong to an enclosing or
+    /// earlier construct and are ignored.
+    fn format_off_line(
+        &self,
+        line_index: usize,
+        first_line_index: usize,
+    ) -> Option<usize> {
+        let format_toggles = self.format_toggles.borrow();
+        let toggles_before =
+            format_toggles.partition_point(|(toggle_line_index, _)| {
+                *toggle_line_index < line_index
+            });
+        match toggles_before
+            .checked_sub(1)
+            .map(|last| format_toggles[last])
+        {
+            Some((off_line_index, false))
+                if off_line_index >= first_line_index =>
+            {
+                Some(off_line_index)
+            }
+            _ => None,
+        }
+    }
+
+    /// If the first of `constructs` is in a region where formatting is turned
+    /// off, copies the source from the [`FORMAT_OFF_MARKER`] through the last
+    /// of `constructs` in the same region. The [`FORMAT_ON_MARKER`] ending the
+    /// region is copied too if only comments come between.
+    fn build_format_off_region<T>(
+        &self,
+        constructs: &[T],
+        span_of: impl Fn(&T) -> Span,
+        first_line_index: usize,
+    ) -> Option<FormatOffRegion> {
+        let is_in_region = |construct: &T, off_line_index| {
+            self.format_off_line(
+                span_of(construct).line_index(self),
+                first_line_index,
+            ) == Some(off_line_index)
+        };
+        let off_line_index = self.format_off_line(
+            span_of(constructs.first()?).line_index(self),
+            first_line_index,
+        )?;
+        let length = constructs
+            .iter()
+            .take_while(|construct| is_in_region(construct, off_line_index))
+            .count();
+
+        let file = self.file.borrow().unwrap();
+        let source = file.source();
+        let line_range = |line_index| {
+            file.line_range((), line_index)
+                .expect("marker was somehow not from the file")
+        };
+        let off_line = &source[line_range(off_line_index)];
+        let start = line_range(off_line_index).start + off_line.len()
+            - off_line.trim_start().len();
+        let mut end = span_of(&constructs[length - 1]).end().to_usize();
+        // the span of a statement may stop short of its semicolon
+        if source[end..].starts_with(';') {
+            end += 1;
+        }
+
+        let format_toggles = self.format_toggles.borrow();
+        let next_toggle = format_toggles.get(format_toggles.partition_point(
+            |(toggle_line_index, _)| *toggle_line_index <= off_line_index,
+        ));
+        if let Some(&(on_line_index, true)) = next_toggle {
+            let on_range = line_range(on_line_index);
+            let only_comments_between = on_range.start >= end
+                && source[end..on_range.start].lines().all(|line| {
+                    let line = line.trim();
+                    line.is_empty() || line.starts_with("//")
+                });
+            if only_comments_between {
+                end = on_range.start + source[on_range].trim_end().len();
+            }
+        }
+
+        Some(FormatOffRegion {
+            idx: self.verbatim(source[start..end].replace("\r\n", "\n")),
+            length,
+            start_line_index: off_line_index,
+            end_line_index: self.line_index_at(end),
+        })
+    }
+
+    /// The source text `span` covers.
+    fn source_text(&self, span: Span) -> &'code str {
+        let file = self.file.borrow().unwrap();
+        &file.source()[span.start().to_usize()..span.end().to_usize()]
+    }
+
+    /// Whether the line at `line_index` is a [`SKIP_MARKER`].
+    fn is_skip_marker(&self, line_index: usize) -> bool {
+        let file = self.file.borrow().unwrap();
+        file.line_range((), line_index)
+            .is_ok_and(|range| file.source()[range].trim() == SKIP_MARKER)
+    }
+
+    /// Whether the construct starting on line `line_index` is preceded by a
+    /// [`SKIP_MARKER`].
+    fn is_skipped(&self, line_index: usize) -> bool {
+        line_index.checked_sub(1).is_some_and(|marker_line_index| {
+            self.is_skip_marker(marker_line_index)
+        })
+    }
+
+    /// Copies the source text `span` covers as is, after the [`SKIP_MARKER`]
+    /// asking for it, which would otherwise be lost with the other comments.
+    fn build_skipped(&self, span: Span) -> DocumentIdx {
+        self.list([
+            self.text(SKIP_MARKER),
+            self.newline(),
+            self.verbatim(self.source_text(span).replace("\r\n", "\n")),
+        ])
+    }
+
+    /// Returns newlines for the blank lines in the source between a construct
+    /// ending on line `last_end_line_index` and one starting on line
+    /// `next_line_index`, up to the configured `max_blank_lines`. A
+    /// [`SKIP_MARKER`] between them is not a blank line.
+    fn blank_lines(
+        &self,
+        last_end_line_index: usize,
+        next_line_index: usize,
+    ) -> Vec<DocumentIdx> {
+        let source_blank_lines = (last_end_line_index + 1..next_line_index)
+            .filter(|line_index| !self.is_skip_marker(*line_index))
+            .count();
+        (0..source_blank_lines.min(self.config.max_blank_lines.inner))
+            .map(|_| self.newline())
+            .collect()
+    }
+
+    /// A line break, or a space when flattened.
+    pub fn newline(&self) -> DocumentIdx {
         self.inner.borrow_mut().add(Document::Newline)
     }
 
-    fn text(&self, text: impl Into<String>) -> DocumentIdx {
-        self.inner.borrow_mut().add(Document::Text(text.into()))
+    /// Text on a single line.
+    pub fn text(&self, text: impl Into<String>) -> DocumentIdx {
+        let text = text.into();
+        // text is measured as a single line; anything spanning lines has to be
+        // verbatim so that the resolver and printer account for its breaks
+        debug_assert!(
+            !text.contains('\n'),
+            "text {text:?} spans lines and must be verbatim"
+        );
+        self.inner.borrow_mut().add(Document::Text(text))
     }
 
-    fn token(&self, text: lexer::TokenKind) -> DocumentIdx {
+    /// Marks `body` as built from the source at `span`.
+    fn spanned(&self, span: Span, body: DocumentIdx) -> DocumentIdx {
+        self.inner.borrow_mut().add(Document::Spanned(
+            span.start().to_usize()..span.end().to_usize(),
+            body,
+        ))
+    }
+
+    /// Text printed exactly as is, which may span lines.
+    pub fn verbatim(&self, text: impl Into<String>) -> DocumentIdx {
+        self.inner.borrow_mut().add(Document::Verbatim(text.into()))
+    }
+
+    /// The text of `text`, a token.
+    pub fn token(&self, text: lexer::TokenKind) -> DocumentIdx {
         self.text(text.as_str())
     }
 
-    fn nest(&self, body: DocumentIdx, by: isize) -> DocumentIdx {
+    /// Indents the lines `body` breaks onto by `by` more columns.
+    pub fn nest(&self, body: DocumentIdx, by: isize) -> DocumentIdx {
         self.inner.borrow_mut().add(Document::Nest(body, by))
     }
 
-    fn flatten(&self, body: DocumentIdx) -> DocumentIdx {
+    /// Lays out `body` on a single line, taking the first alternative of
+    /// every choice.
+    pub fn flatten(&self, body: DocumentIdx) -> DocumentIdx {
         self.inner.borrow_mut().add(Document::Flatten(body))
     }
 
-    fn try_catch(
+    /// Lays out `try_body` if it fits, and `catch_body` otherwise. In
+    /// always-break mode, this is always `catch_body`.
+    pub fn try_catch(
         &self,
         try_body: DocumentIdx,
         catch_body: DocumentIdx,
     ) -> DocumentIdx {
+        if self.always_break {
+            return catch_body;
+        }
         self.inner
             .borrow_mut()
             .add(Document::TryCatch(try_body, catch_body))
     }
 
-    fn list(&self, list: impl IntoIterator<Item = DocumentIdx>) -> DocumentIdx {
+    /// The width the document at `idx` takes up when printed flat, taking the
+    /// first alternative of every choice.
+    fn flat_width(&self, idx: DocumentIdx) -> usize {
+        let store = self.inner.borrow();
+        let mut width = 0;
+        let mut stack = vec![idx];
+        while let Some(idx) = stack.pop() {
+            match store.get(idx) {
+                Document::Newline => width += 1,
+                Document::Text(text) => width += text.width(),
+                Document::Verbatim(text) => {
+                    width += text.lines().next().unwrap_or_default().width();
+                }
+                Document::Nest(body_idx, _)
+                | Document::Flatten(body_idx)
+                | Document::WithWidth(_, body_idx)
+                | Document::Spanned(_, body_idx)
+                | Document::TryCatch(body_idx, _) => stack.push(*body_idx),
+                Document::List(children) => stack.extend(children),
+                Document::Fill(children) => {
+                    width += children.len().saturating_sub(1);
+                    stack.extend(children);
+                }
+            }
+        }
+        width
+    }
+
+    /// Resolves `body` to fit `width` columns, if given, instead of the
+    /// maximum width around it.
+    fn with_width(
+        &self,
+        width: Option<impl Into<usize>>,
+        body: DocumentIdx,
+    ) -> DocumentIdx {
+        match width {
+            Some(width) => self
+                .inner
+                .borrow_mut()
+                .add(Document::WithWidth(width.into(), body)),
+            None => body,
+        }
+    }
+
+    /// Places each of `items` after a space on the current line if it fits
+    /// there, and otherwise on a new line. In always-break mode, every item
+    /// goes on a new line.
+    pub fn fill(
+        &self,
+        items: impl IntoIterator<Item = DocumentIdx>,
+    ) -> DocumentIdx {
+        let items = items.into_iter().collect::<Vec<_>>();
+        if self.always_break {
+            let newline = self.newline();
+            let mut list = vec![];
+            for (i, item) in items.into_iter().enumerate() {
+                if i > 0 {
+                    list.push(newline);
+                }
+                list.push(item);
+            }
+            return self.list(list);
+        }
+        self.inner.borrow_mut().add(Document::Fill(items))
+    }
+
+    /// The documents of `list` one after another.
+    pub fn list(
+        &self,
+        list: impl IntoIterator<Item = DocumentIdx>,
+    ) -> DocumentIdx {
         self.inner
             .borrow_mut()
             .add(Document::List(list.into_iter().collect()))
@@ -1066,39 +2744,117 @@ impl<'code> DocumentBuilder<'code> {
         between: impl Into<Option<lexer::TokenKind>>,
     ) -> (DocumentIdx, DocumentIdx) {
         let between = between.into();
+        let mut contents = contents.into_iter().peekable();
+        // nothing to break, so no newlines either way
+        if contents.peek().is_none() {
+            let empty = self.list([]);
+            return (empty, empty);
+        }
 
         let mut list = vec![];
+        let mut first_line_index = None;
         let mut last_line_index = 0;
-        for (i, (item, item_line_index)) in contents
-            .into_iter()
-            .map(|item| (item.build(self), item.line_index(self)))
-            .enumerate()
-        {
+        let mut last_end_line_index = 0;
+        for (i, item) in contents.into_iter().enumerate() {
+            let item_line_index = item.line_index(self);
+            first_line_index.get_or_insert(item_line_index);
             if i > 0 {
                 if let Some(ref between) = between {
                     list.extend([self.token(between.clone()), self.newline()]);
                 }
-                if last_line_index < item_line_index - 1 {
-                    list.push(self.newline());
-                }
+                list.extend(
+                    self.blank_lines(last_end_line_index, item_line_index),
+                );
             }
-            list.push(item);
+            list.push(item.build(self));
             last_line_index = item_line_index;
+            last_end_line_index = item.end_line_index(self);
         }
         let doc_contents = self.list(list);
+        let is_comma_group = matches!(between, Some(lexer::TokenKind::Comma))
+            && first_line_index.is_some();
+        let trailing_comma = self.config.trailing_comma;
+
+        let flat_doc = if is_comma_group
+            && trailing_comma == TrailingComma::Always
+        {
+            self.flatten(
+                self.list([doc_contents, self.token(lexer::TokenKind::Comma)]),
+            )
+        } else {
+            self.flatten(doc_contents)
+        };
+
         let mut nest_list =
             vec![self.newline(), self.nest(doc_contents, self.indent)];
-        if matches!(between, Some(lexer::TokenKind::Comma)) {
-            // always trailing comma when nesting a comma group, could
-            // overestimate
+        if is_comma_group && trailing_comma != TrailingComma::Never {
             nest_list.push(self.token(lexer::TokenKind::Comma));
         }
         nest_list.push(self.newline());
+        let nest_doc = self.list(nest_list);
+
+        // in minimal mode, never join lines the user already broke
+        if self.config.minimal
+            && first_line_index.is_some_and(|first| first != last_line_index)
+        {
+            return (nest_doc, nest_doc);
+        }
+
         // try to flatten, otherwise nest
-        (self.flatten(doc_contents), self.list(nest_list))
+        (flat_doc, nest_doc)
+    }
+
+    /// Builds generic arguments opened by `open` and closed by `>` like
+    /// [`Self::group`], except that a single argument is never broken onto
+    /// a line of its own, and several that do not fit on one line are filled
+    /// across indented lines, as many to a line as fit.
+    fn generic_group<B: BuildAsDocument + HasLineNumber>(
+        &self,
+        open: &str,
+        arguments: &[B],
+    ) -> DocumentIdx {
+        let close = lexer::TokenKind::Gt.as_str();
+        if let [argument] = arguments {
+            return self.list([
+                self.text(open),
+                argument.build(self),
+                self.text(close),
+            ]);
+        }
+        // in minimal mode, lines the user broke are kept as they are
+        if arguments.is_empty() || self.config.minimal {
+            return self.group(open, arguments, lexer::TokenKind::Comma, close);
+        }
+
+        let mut entries = vec![];
+        for (i, argument) in arguments.iter().enumerate() {
+            let mut entry = vec![argument.build(self)];
+            if i + 1 < arguments.len()
+                || self.config.trailing_comma != TrailingComma::Never
+            {
+                entry.push(self.token(lexer::TokenKind::Comma));
+            }
+            entries.push(self.list(entry));
+        }
+
+        let (flat_idx, _) = self.group_raw(arguments, lexer::TokenKind::Comma);
+        self.try_catch(
+            self.list([self.text(open), flat_idx, self.text(close)]),
+            self.list([
+                self.text(open),
+                self.nest(
+                    self.list([self.newline(), self.fill(entries)]),
+                    self.indent,
+                ),
+                self.newline(),
+                self.text(close),
+            ]),
+        )
     }
 
-    fn group<'a, B: BuildAsDocument + HasLineNumber + 'a>(
+    /// `contents` separated by `between` inside `open` and `close`, on one
+    /// line if it fits and otherwise each on its own indented line.
+    pub fn group<'a, B: BuildAsDocument + HasLineNumber + 'a>(
         &self,
         open: impl Into<String>,
         contents: impl IntoIterator<Item = &'a B>,
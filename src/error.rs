@@ -0,0 +1,132 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{fmt, io, ops::Range};
+
+use camino::Utf8PathBuf;
+use snafu::Snafu;
+use spade_diagnostics::Diagnostic;
+
+/// Everything that can go wrong while formatting Spade code.
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum FormatError {
+    /// The input is not valid Spade. Each diagnostic carries the spans of the
+    /// offending code.
+    #[snafu(display("Failed to parse Spade source"))]
+    Parse { diagnostics: Vec<Diagnostic> },
+
+    /// A configuration file could not be decoded.
+    #[snafu(display("Failed to decode config at {path}"))]
+    Config {
+        path: Utf8PathBuf,
+        source: toml::de::Error,
+    },
+
+    /// A configuration file sets an option that does not exist.
+    #[snafu(display(
+        "Unknown option `{key}` in config at {path}{}",
+        suggestion
+            .as_ref()
+            .map(|suggestion| format!(": did you mean `{suggestion}`?"))
+            .unwrap_or_default()
+    ))]
+    UnknownConfigKey {
+        path: Utf8PathBuf,
+        key: String,
+        suggestion: Option<String>,
+    },
+
+    /// A file could not be read or written.
+    #[snafu(display("Failed to access {path}"))]
+    Io {
+        path: Utf8PathBuf,
+        source: io::Error,
+    },
+
+    /// A directive on the first line of a file sets an option that does not
+    /// exist or to a value it cannot take. `columns` are the one-based,
+    /// half-open columns of the offending option.
+    #[snafu(display(
+        "Invalid spadefmt directive at line 1, columns {}-{}: {message}",
+        columns.start,
+        columns.end
+    ))]
+    Directive {
+        columns: Range<usize>,
+        message: String,
+    },
+
+    /// Formatting the same input in parallel with other inputs produced
+    /// different output than formatting it serially.
+    #[snafu(display(
+        "Formatting {path} is nondeterministic: the parallel run disagreed \
+         with the serial run"
+    ))]
+    Nondeterministic { path: Utf8PathBuf },
+
+    /// A top-level item did not start at column zero or left the printer
+    /// indented once printed, which would misindent the items after it.
+    #[snafu(display(
+        "Item on lines {start_line}-{end_line} left the printer indented by \
+         {indent} spaces"
+    ))]
+    IndentLeak {
+        start_line: usize,
+        end_line: usize,
+        indent: usize,
+    },
+
+    /// Text could not fit within the maximum width in any layout, and the
+    /// configuration asks for that to be an error.
+    #[snafu(display(
+        "Line {line} of the formatted code cannot fit within the maximum \
+         width of {max_width}"
+    ))]
+    Overflow { line: usize, max_width: usize },
+
+    /// The formatted code does not parse to the same syntax tree as the
+    /// input.
+    #[snafu(display(
+        "Formatting changed the meaning of the code starting on line {line}"
+    ))]
+    AstMismatch { line: usize },
+
+    /// `git diff` could not tell which lines of a file changed.
+    #[snafu(display("Failed to diff {path} against {base}: {message}"))]
+    GitDiff {
+        path: Utf8PathBuf,
+        base: String,
+        message: String,
+    },
+
+    /// No item, statement, or expression of the source is at the position
+    /// given, e.g., to `--explain`.
+    #[snafu(display("No item, statement, or expression at {line}:{column}"))]
+    NoConstruct { line: usize, column: usize },
+
+    /// The interactive terminal view could not be drawn or read from.
+    #[snafu(display("Failed to use the terminal"))]
+    Terminal { source: io::Error },
+
+    /// The resolved document could not be printed.
+    #[snafu(display("Failed to print document"))]
+    Print { source: fmt::Error },
+}
+
+impl FormatError {
+    /// Whether the error is due to the input rather than the environment.
+    pub fn is_parse_error(&self) -> bool {
+        matches!(self, Self::Parse { .. })
+    }
+}
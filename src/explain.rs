@@ -0,0 +1,183 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Explaining which layout was chosen for a construct of the source and why.
+
+use std::{collections::HashSet, fmt::Write, ops::Range, str::FromStr};
+
+use snafu::ResultExt;
+use spade_codespan_reporting::files::{Files, SimpleFile};
+
+use crate::{
+    FormatError, build_document,
+    config::Config,
+    document::{Document, DocumentIdx, InternedDocumentStore},
+    error::{NoConstructSnafu, PrintSnafu},
+    resolve_try_catch::{PrintingContext, resolve_try_catch},
+};
+
+/// A position in a source file, as a one-based line and column. Columns count
+/// characters.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SourcePosition {
+    /// The byte offset of this position in `source`, if it is inside it.
+    pub fn offset(&self, source: &str) -> Option<usize> {
+        let line_start = if self.line == 1 {
+            0
+        } else {
+            source
+                .match_indices('\n')
+                .nth(self.line.checked_sub(2)?)
+                .map(|(index, _)| index + 1)?
+        };
+        let line = source[line_start..].split('\n').next().unwrap_or_default();
+        line.char_indices()
+            .map(|(index, _)| index)
+            .chain([line.len()])
+            .nth(self.column.checked_sub(1)?)
+            .map(|index| line_start + index)
+    }
+}
+
+impl FromStr for SourcePosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("invalid position `{s}`: expected line:column");
+        let (line, column) = s.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            line: line.parse().map_err(|_| invalid())?,
+            column: column.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Describes the layout choices made for the innermost item, statement, or
+/// expression of `source` at `position` when formatting it according to
+/// `config`: which alternative was taken at each choice, and whether the
+/// first alternative fit within the maximum width.
+///
+/// Choices belonging to constructs nested inside it are left to those
+/// constructs.
+pub fn explain(
+    source: &str,
+    config: &Config,
+    position: SourcePosition,
+) -> Result<String, FormatError> {
+    let no_construct = || {
+        NoConstructSnafu {
+            line: position.line,
+            column: position.column,
+        }
+        .build()
+    };
+    let offset = position.offset(source).ok_or_else(no_construct)?;
+    let file = SimpleFile::new(String::new(), source.to_string());
+    let (mut document_store, root_idx) = build_document(source, config)?;
+
+    let target = spans(&document_store, root_idx)
+        .into_iter()
+        .filter(|span| span.start <= offset && offset <= span.end)
+        .min_by_key(|span| span.len())
+        .ok_or_else(no_construct)?;
+
+    let mut context = PrintingContext::for_config(config).recording_choices();
+    resolve_try_catch(&mut document_store, root_idx, &mut context);
+
+    let location = file
+        .location((), target.start)
+        .expect("construct was somehow not from the file");
+    let mut lines = source[target.clone()].lines();
+    let first_line = lines.next().unwrap_or_default().trim();
+    let ellipsis = if lines.next().is_some() { " ..." } else { "" };
+
+    let mut explanation = String::new();
+    write!(
+        explanation,
+        "{}:{}: `{first_line}{ellipsis}`",
+        location.line_number, location.column_number
+    )
+    .context(PrintSnafu)?;
+
+    let choices = context
+        .choices()
+        .unwrap_or_default()
+        .iter()
+        .filter(|choice| choice.span.as_ref() == Some(&target))
+        .collect::<Vec<_>>();
+    if choices.is_empty() {
+        write!(
+            explanation,
+            "\n  no layout choices: it is laid out the same way at any width"
+        )
+        .context(PrintSnafu)?;
+    }
+    for choice in choices {
+        if choice.kept_try {
+            write!(
+                explanation,
+                "\n  at column {}: kept the first layout, which ended at \
+                 column {} within the maximum width of {}",
+                choice.start_column + 1,
+                choice.end_column + 1,
+                choice.max_width
+            )
+        } else {
+            write!(
+                explanation,
+                "\n  at column {}: fell back to the next layout, since the \
+                 first overflowed the maximum width of {}",
+                choice.start_column + 1,
+                choice.max_width
+            )
+        }
+        .context(PrintSnafu)?;
+    }
+
+    Ok(explanation)
+}
+
+/// The source spans of the constructs the document at `idx` was built from.
+fn spans(store: &InternedDocumentStore, idx: DocumentIdx) -> Vec<Range<usize>> {
+    let mut spans = vec![];
+    let mut visited = HashSet::new();
+    let mut stack = vec![idx];
+    while let Some(idx) = stack.pop() {
+        if !visited.insert(idx) {
+            continue;
+        }
+        match store.get(idx) {
+            Document::Newline | Document::Text(_) | Document::Verbatim(_) => {}
+            Document::Nest(body_idx, _)
+            | Document::Flatten(body_idx)
+            | Document::WithWidth(_, body_idx) => stack.push(*body_idx),
+            Document::Spanned(span, body_idx) => {
+                spans.push(span.clone());
+                stack.push(*body_idx);
+            }
+            Document::List(children) | Document::Fill(children) => {
+                stack.extend(children)
+            }
+            Document::TryCatch(try_body_idx, catch_body_idx) => {
+                stack.extend([*try_body_idx, *catch_body_idx]);
+            }
+        }
+    }
+    spans
+}
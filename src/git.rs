@@ -0,0 +1,79 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Asking git which lines of a file have changed, so that only those parts
+//! need to be formatted.
+
+use std::{ops::Range, process::Command};
+
+use camino::Utf8Path;
+use snafu::{ResultExt, ensure};
+
+use crate::error::{FormatError, GitDiffSnafu, IoSnafu};
+
+/// The lines of `file` that differ from its contents at the git revision
+/// `base`, as zero-based, half-open ranges of line indices into the current
+/// contents. Deleted lines mark the line before the deletion as changed.
+///
+/// A file git does not track has no changed lines.
+pub fn changed_lines(
+    file: &Utf8Path,
+    base: &str,
+) -> Result<Vec<Range<usize>>, FormatError> {
+    let directory = file
+        .parent()
+        .filter(|parent| !parent.as_str().is_empty())
+        .unwrap_or(Utf8Path::new("."));
+    let file_name = file.file_name().unwrap_or(file.as_str());
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(["diff", "--no-color", "--no-ext-diff", "--unified=0", base])
+        .arg("--")
+        .arg(file_name)
+        .output()
+        .context(IoSnafu { path: file })?;
+    ensure!(
+        output.status.success(),
+        GitDiffSnafu {
+            path: file,
+            base,
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+    );
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_hunk_header)
+        .collect())
+}
+
+/// Parses the new-file side of a hunk header such as `@@ -3,2 +4,5 @@` into
+/// the range of line indices it covers.
+fn parse_hunk_header(line: &str) -> Option<Range<usize>> {
+    let new_side = line
+        .strip_prefix("@@ ")?
+        .split(' ')
+        .find_map(|part| part.strip_prefix('+'))?;
+    let (start, count) = match new_side.split_once(',') {
+        Some((start, count)) => {
+            (start.parse::<usize>().ok()?, count.parse().ok()?)
+        }
+        None => (new_side.parse::<usize>().ok()?, 1),
+    };
+    // hunk lines are one-based, and a pure deletion starts at the line before
+    // it, which may be line zero
+    let start_index = start.saturating_sub(1);
+    Some(start_index..start_index + count.max(1))
+}
@@ -0,0 +1,65 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Matching paths against the glob patterns of ignore files and
+//! `.editorconfig` section headers.
+
+/// Whether `pattern` matches all of `text`, a path with `/` separators. In
+/// `pattern`, `*` matches within a path component, `**` matches across
+/// components, with `**/` also matching no components at all, `?` matches
+/// one character other than `/`, `[...]`/`[!...]` match a character in or
+/// not in a set, and `\` makes the character after it literal.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    matches_chars(&pattern, &text)
+}
+
+fn matches_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', '/', rest @ ..] => {
+            matches_chars(rest, text)
+                || (0..text.len()).any(|i| {
+                    text[i] == '/' && matches_chars(rest, &text[i + 1..])
+                })
+        }
+        ['*', '*', rest @ ..] => {
+            (0..=text.len()).any(|i| matches_chars(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| matches_chars(rest, &text[i..])),
+        ['?', rest @ ..] => match text {
+            [c, text @ ..] if *c != '/' => matches_chars(rest, text),
+            _ => false,
+        },
+        ['[', class @ ..] if class.contains(&']') => {
+            let end = class.iter().position(|&c| c == ']').unwrap_or_default();
+            let (negated, set) = match &class[..end] {
+                ['!', set @ ..] => (true, set),
+                set => (false, set),
+            };
+            match text {
+                [c, text @ ..] if set.contains(c) != negated => {
+                    matches_chars(&class[end + 1..], text)
+                }
+                _ => false,
+            }
+        }
+        ['\\', literal, rest @ ..] | [literal, rest @ ..] => match text {
+            [c, text @ ..] if c == literal => matches_chars(rest, text),
+            _ => false,
+        },
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Syntax highlighting formatted code for the terminal.
+
+use std::{fs, io, ops::Range, str::FromStr};
+
+use camino::Utf8Path;
+use serde::{Deserialize, Deserializer, de};
+use snafu::ResultExt;
+use spade_codespan_reporting::term::termcolor::{Color, ColorSpec, WriteColor};
+use spade_parser::{lexer::TokenKind, logos::Logos};
+
+use crate::error::{ConfigSnafu, FormatError, IoSnafu};
+
+/// Words highlighted as keywords.
+const KEYWORDS: &[&str] = &[
+    "as", "assert", "decl", "else", "entity", "enum", "extern", "false", "fn",
+    "for", "gen", "if", "impl", "initial", "inst", "let", "lib", "match",
+    "mod", "mut", "pipeline", "port", "reg", "reset", "self", "set", "stage",
+    "struct", "trait", "true", "type", "unsafe", "use", "where",
+];
+
+/// The colors to highlight each kind of token with, as read from a theme
+/// file. Each color is written like `"magenta"` or `"bold 208"`: an optional
+/// `bold` followed by a color name, an ANSI color number, or `r,g,b`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Theme {
+    #[serde(default = "default_keyword", deserialize_with = "color_spec")]
+    pub keyword: ColorSpec,
+    #[serde(default, deserialize_with = "color_spec")]
+    pub identifier: ColorSpec,
+    #[serde(default = "default_number", deserialize_with = "color_spec")]
+    pub number: ColorSpec,
+    #[serde(default = "default_string", deserialize_with = "color_spec")]
+    pub string: ColorSpec,
+    #[serde(default, deserialize_with = "color_spec")]
+    pub punctuation: ColorSpec,
+    #[serde(default = "default_comment", deserialize_with = "color_spec")]
+    pub comment: ColorSpec,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            keyword: default_keyword(),
+            identifier: ColorSpec::new(),
+            number: default_number(),
+            string: default_string(),
+            punctuation: ColorSpec::new(),
+            comment: default_comment(),
+        }
+    }
+}
+
+impl Theme {
+    /// Reads and decodes the theme file at `path`. Kinds of tokens it does
+    /// not set keep their default colors.
+    pub fn from_path(path: &Utf8Path) -> Result<Self, FormatError> {
+        let contents = fs::read_to_string(path).context(IoSnafu { path })?;
+        toml::from_str(&contents).context(ConfigSnafu { path })
+    }
+
+    /// The colors to highlight text of `group` with.
+    pub fn color_spec(&self, group: HighlightGroup) -> &ColorSpec {
+        match group {
+            HighlightGroup::Keyword => &self.keyword,
+            HighlightGroup::Identifier => &self.identifier,
+            HighlightGroup::Number => &self.number,
+            HighlightGroup::String => &self.string,
+            HighlightGroup::Punctuation => &self.punctuation,
+            HighlightGroup::Comment => &self.comment,
+        }
+    }
+}
+
+fn default_keyword() -> ColorSpec {
+    ColorSpec::new()
+        .set_fg(Some(Color::Magenta))
+        .set_bold(true)
+        .clone()
+}
+
+fn default_number() -> ColorSpec {
+    ColorSpec::new().set_fg(Some(Color::Yellow)).clone()
+}
+
+fn default_string() -> ColorSpec {
+    ColorSpec::new().set_fg(Some(Color::Green)).clone()
+}
+
+fn default_comment() -> ColorSpec {
+    ColorSpec::new().set_fg(Some(Color::Blue)).clone()
+}
+
+fn color_spec<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<ColorSpec, D::Error> {
+    let spec = String::deserialize(deserializer)?;
+    let (bold, color) = match spec.trim().strip_prefix("bold") {
+        Some(color) => (true, color.trim()),
+        None => (false, spec.trim()),
+    };
+    let mut color_spec = ColorSpec::new();
+    color_spec.set_bold(bold);
+    if !color.is_empty() {
+        color_spec.set_fg(Some(Color::from_str(color).map_err(|error| {
+            de::Error::custom(format!("invalid color `{color}`: {error}"))
+        })?));
+    }
+    Ok(color_spec)
+}
+
+/// What kind of text a span of code is, which determines how it is
+/// highlighted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HighlightGroup {
+    Keyword,
+    Identifier,
+    Number,
+    String,
+    Punctuation,
+    /// Text the lexer skips other than whitespace.
+    Comment,
+}
+
+/// Classifies the text of `source` for highlighting, returning the byte range
+/// of each token and of each line of comment in order. Whitespace is not
+/// part of any range.
+pub fn highlight_spans(source: &str) -> Vec<(Range<usize>, HighlightGroup)> {
+    let mut spans = vec![];
+    let mut classified_up_to = 0;
+    let mut lexer = TokenKind::lexer(source);
+    while lexer.next().is_some() {
+        let span = lexer.span();
+        add_comment_spans(source, classified_up_to..span.start, &mut spans);
+
+        let token = lexer.slice();
+        let group = if KEYWORDS.contains(&token) {
+            HighlightGroup::Keyword
+        } else if token.starts_with(|c: char| c.is_ascii_digit()) {
+            HighlightGroup::Number
+        } else if token.starts_with('"') {
+            HighlightGroup::String
+        } else if token.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            HighlightGroup::Identifier
+        } else {
+            HighlightGroup::Punctuation
+        };
+        classified_up_to = span.end;
+        spans.push((span, group));
+    }
+    add_comment_spans(source, classified_up_to..source.len(), &mut spans);
+    spans
+}
+
+/// Adds the range of each line of text in `gap`, a range of `source` between
+/// tokens, to `spans` as a comment.
+fn add_comment_spans(
+    source: &str,
+    gap: Range<usize>,
+    spans: &mut Vec<(Range<usize>, HighlightGroup)>,
+) {
+    let mut line_start = gap.start;
+    for line in source[gap].split_inclusive('\n') {
+        let text = line.trim_start();
+        let start = line_start + (line.len() - text.len());
+        let text = text.trim_end();
+        if !text.is_empty() {
+            spans.push((start..start + text.len(), HighlightGroup::Comment));
+        }
+        line_start += line.len();
+    }
+}
+
+/// Writes `code` to `output`, highlighting it according to `theme` as
+/// classified by [`highlight_spans`].
+pub fn highlight(
+    code: &str,
+    theme: &Theme,
+    output: &mut impl WriteColor,
+) -> io::Result<()> {
+    let mut written_up_to = 0;
+    for (span, group) in highlight_spans(code) {
+        output.write_all(code[written_up_to..span.start].as_bytes())?;
+        output.set_color(theme.color_spec(group))?;
+        output.write_all(code[span.clone()].as_bytes())?;
+        output.reset()?;
+        written_up_to = span.end;
+    }
+    output.write_all(code[written_up_to..].as_bytes())
+}
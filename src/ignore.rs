@@ -0,0 +1,135 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Skipping files listed in a [`IGNORE_FILE_NAME`] or under the `ignore` key
+//! of the configuration.
+//!
+//! Patterns follow `.gitignore` syntax, matched by [`crate::glob::matches`]:
+//! `*`, `?`, and `[...]` match within a path component and `**` matches any
+//! number of components; a pattern containing a `/` other than at its end is
+//! relative to the directory it was written in, and otherwise matches at any
+//! depth below it; a trailing `/` matches only directories; and a leading `!`
+//! brings back what an earlier pattern skipped. Lines of an ignore file that
+//! are blank or start with `#` are ignored.
+
+use std::fs;
+
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use snafu::ResultExt;
+
+use crate::{
+    config::discover::ConfigCache,
+    error::{FormatError, IoSnafu},
+    glob,
+};
+
+/// The name of the ignore file searched for by [`is_ignored`].
+pub const IGNORE_FILE_NAME: &str = ".spadefmtignore";
+
+/// Rewrites `pattern`, written in `directory`, into one anchored at the
+/// absolute path of `directory`, so that it no longer depends on where it
+/// was written.
+pub fn anchor(directory: &Utf8Path, pattern: &str) -> String {
+    let directory = absolute(directory);
+    let (negation, pattern) = match pattern.strip_prefix('!') {
+        Some(pattern) => ("!", pattern),
+        None => ("", pattern),
+    };
+    let is_relative = pattern.trim_end_matches('/').contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    if is_relative {
+        format!("{negation}{directory}/{pattern}")
+    } else {
+        format!("{negation}{directory}/**/{pattern}")
+    }
+}
+
+/// Whether `file` is skipped by the nearest [`IGNORE_FILE_NAME`] in its
+/// directory or an ancestor, or by the configuration that applies to it,
+/// which is read from `config_path` if given and otherwise discovered through
+/// `configs`.
+pub fn is_ignored(
+    file: &Utf8Path,
+    config_path: Option<&Utf8Path>,
+    configs: &ConfigCache,
+) -> Result<bool, FormatError> {
+    let mut patterns = vec![];
+    if let Some(ignore_file) = find_ignore_file(file) {
+        let contents = fs::read_to_string(&ignore_file)
+            .context(IoSnafu { path: &ignore_file })?;
+        let directory = ignore_file.parent().unwrap_or(Utf8Path::new(""));
+        patterns.extend(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| anchor(directory, line)),
+        );
+    }
+    patterns.extend(configs.discover(file, config_path)?.ignore);
+
+    let file = absolute(file);
+    let mut ignored = false;
+    for pattern in &patterns {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern.as_str()),
+        };
+        if matches_file_or_ancestor(pattern, &file) {
+            ignored = !negated;
+        }
+    }
+    Ok(ignored)
+}
+
+/// Finds the nearest [`IGNORE_FILE_NAME`] in the directory of `file` or one
+/// of its ancestors.
+fn find_ignore_file(file: &Utf8Path) -> Option<Utf8PathBuf> {
+    absolute(file.parent().unwrap_or(Utf8Path::new("")))
+        .ancestors()
+        .map(|ancestor| ancestor.join(IGNORE_FILE_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+/// `path` made absolute and with symbolic links resolved, if it exists.
+fn absolute(path: &Utf8Path) -> Utf8PathBuf {
+    let path = if path.as_str().is_empty() {
+        Utf8Path::new(".")
+    } else {
+        path
+    };
+    path.canonicalize_utf8()
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Whether the anchored `pattern` matches `file` itself or, as it is then
+/// inside a skipped directory, one of its ancestors.
+fn matches_file_or_ancestor(pattern: &str, file: &Utf8Path) -> bool {
+    let directory_only = pattern.ends_with('/');
+    let pattern = pattern
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    let components = file
+        .components()
+        .filter_map(|component| match component {
+            Utf8Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    // a directory-only pattern never matches the file itself
+    let longest = components.len() - usize::from(directory_only);
+    (1..=longest)
+        .any(|length| glob::matches(&pattern, &components[..length].join("/")))
+}
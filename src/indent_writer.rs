@@ -0,0 +1,159 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+
+/// A writer that indents every non-empty line by an exact number of spaces.
+///
+/// Unlike [`inform::fmt::IndentWriter`], which indents in whole levels, the
+/// indentation here is changed by arbitrary (including negative) deltas, so
+/// that it can mirror [`crate::document::Document::Nest`] exactly.
+pub struct IndentWriter<W: fmt::Write> {
+    inner: W,
+    indent: usize,
+    at_line_start: bool,
+}
+
+impl<W: fmt::Write> IndentWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            indent: 0,
+            at_line_start: true,
+        }
+    }
+
+    /// The underlying writer.
+    pub fn inner(&self) -> &W {
+        &self.inner
+    }
+
+    /// The number of spaces lines are currently indented by.
+    pub fn indent(&self) -> usize {
+        self.indent
+    }
+
+    /// Changes the indentation of subsequent lines by `by` spaces. Returns
+    /// `None` and leaves the indentation unchanged if it would become
+    /// negative.
+    pub fn indent_by(&mut self, by: isize) -> Option<()> {
+        self.indent = self.indent.checked_add_signed(by)?;
+        Some(())
+    }
+
+    /// Writes `text` indenting only its first line, so that text copied from
+    /// the source keeps the layout it had there.
+    pub fn write_verbatim(&mut self, text: &str) -> fmt::Result {
+        let Some((first_line, rest)) = text.split_once('\n') else {
+            return fmt::Write::write_str(self, text);
+        };
+        fmt::Write::write_str(self, first_line)?;
+        fmt::Write::write_char(&mut self.inner, '\n')?;
+        fmt::Write::write_str(&mut self.inner, rest)?;
+        self.at_line_start = rest.is_empty() || rest.ends_with('\n');
+        Ok(())
+    }
+}
+
+impl<W: fmt::Write> fmt::Write for IndentWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.inner.write_char('\n')?;
+                self.at_line_start = true;
+            }
+            if !line.is_empty() {
+                if self.at_line_start {
+                    write!(self.inner, "{:1$}", "", self.indent)?;
+                    self.at_line_start = false;
+                }
+                self.inner.write_str(line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use super::*;
+    use crate::document::{Document, InternedDocumentStore, print_resolved};
+
+    #[test]
+    fn indent_by_applies_exact_deltas() {
+        let mut buffer = String::new();
+        let mut f = IndentWriter::new(&mut buffer);
+        f.indent_by(4).unwrap();
+        writeln!(f, "a").unwrap();
+        f.indent_by(3).unwrap();
+        writeln!(f, "b").unwrap();
+        f.indent_by(-5).unwrap();
+        writeln!(f, "c").unwrap();
+        // going below zero is refused and keeps the indentation as it was
+        assert_eq!(f.indent_by(-3), None);
+        assert_eq!(f.indent(), 2);
+        write!(f, "d").unwrap();
+
+        assert_eq!(buffer, "    a\n       b\n  c\n  d");
+    }
+
+    #[test]
+    fn nested_nests_print_with_exact_indentation() {
+        let mut store = InternedDocumentStore::default();
+        let newline = store.add(Document::Newline);
+        let [x, a, b, c, d] = ["x", "a", "b", "c", "d"]
+            .map(|text| store.add(Document::Text(text.into())));
+        let innermost = store.add(Document::List(vec![newline, c]));
+        let dedent = store.add(Document::Nest(innermost, -5));
+        let middle = store.add(Document::List(vec![newline, b, dedent]));
+        let indent = store.add(Document::Nest(middle, 3));
+        let outer =
+            store.add(Document::List(vec![newline, a, indent, newline, d]));
+        let nest = store.add(Document::Nest(outer, 4));
+        let root = store.add(Document::List(vec![x, nest, newline, x]));
+
+        let mut buffer = String::new();
+        print_resolved(
+            &store,
+            &mut IndentWriter::new(&mut buffer),
+            root,
+            false,
+            &mut false,
+        )
+        .unwrap();
+
+        assert_eq!(buffer, "x\n    a\n       b\n  c\n    d\nx");
+    }
+
+    #[test]
+    #[should_panic(expected = "which is negative")]
+    fn nest_below_zero_panics() {
+        let mut store = InternedDocumentStore::default();
+        let newline = store.add(Document::Newline);
+        let text = store.add(Document::Text("x".into()));
+        let body = store.add(Document::List(vec![newline, text]));
+        let dedent = store.add(Document::Nest(body, -2));
+        let root = store.add(Document::Nest(dedent, 1));
+
+        let mut buffer = String::new();
+        let _ = print_resolved(
+            &store,
+            &mut IndentWriter::new(&mut buffer),
+            root,
+            false,
+            &mut false,
+        );
+    }
+}
@@ -13,8 +13,459 @@
 
 #![forbid(unsafe_code)]
 
+use std::{fmt::Write, ops::Range, panic, thread};
+
+use snafu::ResultExt;
+use spade_ast as ast;
+use spade_codespan_reporting::files::{Files, SimpleFile};
+use spade_parser::logos::Logos;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    config::{Config, Overflow},
+    document::{DocumentIdx, InternedDocumentStore},
+    document_builder::{DocumentBuilder, full_span_of_item, span_of_item},
+    error::{IndentLeakSnafu, OverflowSnafu, ParseSnafu, PrintSnafu},
+    indent_writer::IndentWriter,
+    resolve_try_catch::{PrintingContext, resolve_try_catch},
+};
+
+pub mod advisory;
+pub mod cache;
 pub mod cli;
 pub mod config;
 pub mod document;
 pub mod document_builder;
+pub mod error;
+pub mod explain;
+pub mod git;
+pub mod glob;
+pub mod highlight;
+pub mod ignore;
+pub mod indent_writer;
+pub mod lsp;
+pub mod output;
+pub mod play;
+pub mod recover;
+pub mod report;
 pub mod resolve_try_catch;
+pub mod stats;
+pub mod swim;
+pub mod verify;
+pub mod version;
+
+pub use error::FormatError;
+
+/// The file ID given to the parser for sources formatted through the library
+/// API. See [`parse_file`] to parse under another ID.
+pub const FILE_ID: usize = 0;
+
+/// Sources with a line wider than this many columns are treated as
+/// minified or machine-generated and built in always-break mode.
+pub const WIDE_INPUT_LINE_LENGTH: usize = 1000;
+
+/// Information about this build of `spadefmt`, including the version of the
+/// Spade parser it was built against.
+pub fn version_info() -> version::VersionInfo {
+    version::VERSION_INFO
+}
+
+/// Parses `source` as the body of a top-level module.
+pub fn parse(source: &str) -> Result<ast::ModuleBody, FormatError> {
+    parse_file(source, FILE_ID)
+}
+
+/// Parses `source` like [`parse`], with its spans and diagnostics pointing
+/// into the file with ID `file_id`.
+pub fn parse_file(
+    source: &str,
+    file_id: usize,
+) -> Result<ast::ModuleBody, FormatError> {
+    let _span = tracing::info_span!("parse", bytes = source.len()).entered();
+    let mut parser = spade_parser::Parser::new(
+        spade_parser::lexer::TokenKind::lexer(source),
+        file_id,
+    );
+
+    parser.top_level_module_body().or_else(|error| {
+        let mut diagnostics = vec![error];
+        diagnostics.append(&mut parser.diags.errors);
+        tracing::debug!(errors = diagnostics.len(), "parse failed");
+        ParseSnafu { diagnostics }.fail()
+    })
+}
+
+/// Parses `source` and builds its (unresolved) document according to
+/// `config`.
+pub fn build_document(
+    source: &str,
+    config: &Config,
+) -> Result<(InternedDocumentStore, DocumentIdx), FormatError> {
+    let root = parse(source)?;
+
+    let _span =
+        tracing::info_span!("build", items = root.members.len()).entered();
+    let file = SimpleFile::new(String::new(), source.to_string());
+    let (document_store, root_idx) =
+        builder_for(source, config).build_root(&root, &file);
+    tracing::debug!(documents = document_store.len(), "built document");
+
+    Ok((document_store, root_idx))
+}
+
+/// The builder that lays out `source` according to `config`, the same way
+/// for every path that formats it, whether whole or item by item.
+pub fn builder_for<'code>(
+    source: &str,
+    config: &Config,
+) -> DocumentBuilder<'code> {
+    let always_break = is_wide_input(source);
+    if always_break {
+        tracing::debug!("wide input, building in always-break mode");
+    }
+    DocumentBuilder::new(config).always_break(always_break)
+}
+
+/// The size of the stack [`with_deep_stack`] runs its work on: enough for the
+/// recursive descent of the parser and builder through code nested ten
+/// thousand levels deep. Only the pages actually used are committed.
+pub const DEEP_STACK_SIZE: usize = 1 << 30;
+
+/// Runs `work` on a thread with a stack of [`DEEP_STACK_SIZE`], so that
+/// parsing and building deeply nested code, e.g., from a generator, cannot
+/// overflow the stack of the calling thread. Panics in `work` are resumed on
+/// the calling thread.
+pub fn with_deep_stack<T: Send>(work: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .name("spadefmt".into())
+            .stack_size(DEEP_STACK_SIZE)
+            .spawn_scoped(scope, work)
+            .expect("failed to spawn a thread to format on")
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    })
+}
+
+/// Whether `source` has a line so long that exploring flat alternatives for
+/// it would be pathological, e.g., a minified single-line file.
+pub fn is_wide_input(source: &str) -> bool {
+    source
+        .lines()
+        .any(|line| line.width() > WIDE_INPUT_LINE_LENGTH)
+}
+
+/// Formats the Spade code in `source` according to `config`.
+pub fn format_source(
+    source: &str,
+    config: &Config,
+) -> Result<String, FormatError> {
+    with_deep_stack(|| {
+        let (mut document_store, root_idx) = build_document(source, config)?;
+        resolve_and_print(&mut document_store, root_idx, config)
+    })
+}
+
+/// Formats `source` like [`format_source`], also returning how many groups had
+/// to be broken across lines because they did not fit.
+pub fn format_source_counting_breaks(
+    source: &str,
+    config: &Config,
+) -> Result<(String, usize), FormatError> {
+    with_deep_stack(|| {
+        let (mut document_store, root_idx) = build_document(source, config)?;
+        resolve_and_print_counting_breaks(&mut document_store, root_idx, config)
+    })
+}
+
+/// Resolves the document at `root_idx` to fit `config.max_width` and prints
+/// it.
+pub fn resolve_and_print(
+    document_store: &mut InternedDocumentStore,
+    root_idx: DocumentIdx,
+    config: &Config,
+) -> Result<String, FormatError> {
+    resolve_and_print_counting_breaks(document_store, root_idx, config)
+        .map(|(formatted, _)| formatted)
+}
+
+/// Resolves and prints the document at `root_idx` like [`resolve_and_print`],
+/// also returning how many groups had to be broken across lines because they
+/// did not fit.
+pub fn resolve_and_print_counting_breaks(
+    document_store: &mut InternedDocumentStore,
+    root_idx: DocumentIdx,
+    config: &Config,
+) -> Result<(String, usize), FormatError> {
+    let (new_root_idx, backtracks) = {
+        let _span = tracing::info_span!("resolve").entered();
+        let mut context = PrintingContext::for_config(config);
+        let new_root_idx =
+            resolve_try_catch(document_store, root_idx, &mut context);
+        check_unavoidable_overflow(&context, config, 0)?;
+        tracing::debug!(
+            backtracks = context.backtracks(),
+            documents = document_store.len(),
+            "resolved document"
+        );
+        (new_root_idx, context.backtracks())
+    };
+
+    debug_assert_eq!(
+        document::check_resolved(document_store, new_root_idx),
+        Ok(())
+    );
+
+    let _span = tracing::info_span!("print").entered();
+    let mut buffer = String::new();
+    let mut f = IndentWriter::new(&mut buffer);
+    document::print_resolved(
+        document_store,
+        &mut f,
+        new_root_idx,
+        false,
+        &mut false,
+    )
+    .context(PrintSnafu)?;
+    tracing::debug!(bytes = buffer.len(), "printed document");
+
+    Ok((buffer, backtracks))
+}
+
+/// Fails if resolving in `context` ran into text that no layout could fit
+/// and `config` makes that an error. `first_line` is the zero-based line of
+/// the output the resolved document starts on.
+fn check_unavoidable_overflow(
+    context: &PrintingContext,
+    config: &Config,
+    first_line: usize,
+) -> Result<(), FormatError> {
+    match context.unavoidable_overflows().first() {
+        Some(overflow) if config.overflow == Overflow::Error => OverflowSnafu {
+            line: first_line + overflow.line + 1,
+            max_width: overflow.max_width,
+        }
+        .fail(),
+        _ => Ok(()),
+    }
+}
+
+/// Formats only the top-level items of `source` that overlap one of
+/// `changed_lines`, zero-based, half-open ranges of line indices, and copies
+/// everything else byte for byte. The result is ready to be written out as
+/// is; see [`git::changed_lines`].
+///
+/// Items nested in a top-level item are not considered on their own, so a
+/// change inside a `mod` reformats the whole module.
+pub fn format_changed_items(
+    source: &str,
+    config: &Config,
+    changed_lines: &[Range<usize>],
+) -> Result<String, FormatError> {
+    with_deep_stack(|| {
+        let root = parse(source)?;
+        let file = SimpleFile::new(String::new(), source.to_string());
+        let (mut document_store, item_idxs) =
+            builder_for(source, config).build_root_items(&root, &file);
+        let line_ending = output::line_ending(config.newline_style, source);
+        let line_of =
+            |byte_index: usize| file.line_index((), byte_index).unwrap_or(0);
+
+        let mut buffer = String::new();
+        let mut copied_up_to = 0;
+        for (item, item_idx) in root.members.iter().zip(item_idxs) {
+            let span = full_span_of_item(item);
+            let (start, end) = (span.start().to_usize(), span.end().to_usize());
+            let lines = line_of(start)..line_of(end) + 1;
+            let is_changed = changed_lines.iter().any(|changed| {
+                changed.start < lines.end && lines.start < changed.end
+            });
+            if !is_changed {
+                continue;
+            }
+            tracing::debug!(?lines, "formatting changed item");
+
+            let mut context = PrintingContext::for_config(config);
+            let new_item_idx =
+                resolve_try_catch(&mut document_store, item_idx, &mut context);
+            let first_line = buffer.matches('\n').count()
+                + source[copied_up_to..start].matches('\n').count();
+            check_unavoidable_overflow(&context, config, first_line)?;
+            let mut formatted_item = String::new();
+            document::print_resolved(
+                &document_store,
+                &mut IndentWriter::new(&mut formatted_item),
+                new_item_idx,
+                false,
+                &mut false,
+            )
+            .context(PrintSnafu)?;
+
+            buffer.push_str(&source[copied_up_to..start]);
+            buffer.push_str(&formatted_item.replace('\n', line_ending));
+            copied_up_to = end;
+        }
+        buffer.push_str(&source[copied_up_to..]);
+
+        Ok(buffer)
+    })
+}
+
+/// Where formatting `source` according to `config` changes it, i.e., a byte
+/// range of `source` that differs from the output of [`format_source`]
+/// rendered by [`output::render`], or `None` if nothing does.
+///
+/// Each top-level item is formatted on its own first, stopping at the first
+/// one whose formatted code differs from its source, whose span is returned,
+/// so a file that is not formatted is usually caught without formatting all
+/// of it. Only if every item is unchanged is the whole file formatted, to
+/// compare what lies between the items.
+pub fn formatting_changes(
+    source: &str,
+    config: &Config,
+) -> Result<Option<Range<usize>>, FormatError> {
+    with_deep_stack(|| {
+        let root = parse(source)?;
+        let file = SimpleFile::new(String::new(), source.to_string());
+        let (mut document_store, item_idxs) =
+            builder_for(source, config).build_root_items(&root, &file);
+        let line_ending = output::line_ending(config.newline_style, source);
+
+        for (item, item_idx) in root.members.iter().zip(item_idxs) {
+            let span = full_span_of_item(item);
+            let (start, end) = (span.start().to_usize(), span.end().to_usize());
+
+            let mut context = PrintingContext::for_config(config);
+            let new_item_idx =
+                resolve_try_catch(&mut document_store, item_idx, &mut context);
+            let first_line = file.line_index((), start).unwrap_or(0);
+            check_unavoidable_overflow(&context, config, first_line)?;
+            let mut formatted_item = String::new();
+            document::print_resolved(
+                &document_store,
+                &mut IndentWriter::new(&mut formatted_item),
+                new_item_idx,
+                false,
+                &mut false,
+            )
+            .context(PrintSnafu)?;
+
+            if formatted_item.replace('\n', line_ending) != source[start..end] {
+                tracing::debug!(line = first_line + 1, "found changed item");
+                return Ok(Some(start..end));
+            }
+        }
+
+        let formatted = format_source(source, config)?;
+        Ok(output::changed_range(
+            source,
+            &output::render(&formatted, source, config),
+        ))
+    })
+}
+
+/// The smallest `max_width` at which `source` formats with no line wider
+/// than it, with the rest of `config` as is.
+///
+/// Widths are binary searched, assuming that a wider limit never leaves more
+/// lines over it. The search starts from `config.max_width`, widened to the
+/// widest formatted line until every line fits.
+pub fn fit_width(source: &str, config: &Config) -> Result<usize, FormatError> {
+    let mut high = config.max_width.inner;
+    let mut config = config.clone();
+    config.overflow = Overflow::PreferBreak;
+    let mut widest_line_at = |max_width: usize| {
+        config.max_width.inner = max_width;
+        format_source(source, &config).map(|formatted| {
+            formatted.lines().map(str::width).max().unwrap_or_default()
+        })
+    };
+
+    loop {
+        let widest_line = widest_line_at(high)?;
+        if widest_line <= high {
+            break;
+        }
+        high = widest_line;
+    }
+    let mut low = 1;
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if widest_line_at(middle)? <= middle {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    tracing::debug!(max_width = high, "found fitting width");
+
+    Ok(high)
+}
+
+/// Prints every top-level item of `source` in turn through one printer,
+/// checking that each starts at column zero and leaves the indentation as it
+/// found it, so that no item can misindent the ones after it.
+pub fn audit_item_indentation(
+    source: &str,
+    config: &Config,
+) -> Result<(), FormatError> {
+    let root = parse(source)?;
+    let file = SimpleFile::new(String::new(), source.to_string());
+    let (mut document_store, item_idxs) =
+        builder_for(source, config).build_root_items(&root, &file);
+
+    let mut buffer = String::new();
+    let mut f = IndentWriter::new(&mut buffer);
+    for (item, item_idx) in root.members.iter().zip(item_idxs) {
+        let new_item_idx = resolve_try_catch(
+            &mut document_store,
+            item_idx,
+            &mut PrintingContext::for_config(config),
+        );
+        let item_start = f.inner().len();
+        document::print_resolved(
+            &document_store,
+            &mut f,
+            new_item_idx,
+            false,
+            &mut false,
+        )
+        .context(PrintSnafu)?;
+
+        let leading_spaces = f.inner()[item_start..]
+            .chars()
+            .take_while(|c| *c == ' ')
+            .count();
+        let indent = f.indent().max(leading_spaces);
+        if indent != 0 {
+            let span = span_of_item(item);
+            let line_of = |byte_index: usize| {
+                file.line_index((), byte_index).map_or(0, |index| index + 1)
+            };
+            return IndentLeakSnafu {
+                start_line: line_of(span.start().to_usize()),
+                end_line: line_of(span.end().to_usize()),
+                indent,
+            }
+            .fail();
+        }
+        writeln!(f).context(PrintSnafu)?;
+    }
+
+    Ok(())
+}
+
+/// Computes a stable hash of `formatted`, e.g., for build systems to detect
+/// formatting drift.
+///
+/// The hash is the 64-bit FNV-1a hash of the UTF-8 bytes of `formatted`, so
+/// it does not depend on the platform or the version of Rust `spadefmt` was
+/// built with.
+pub fn content_hash(formatted: &str) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    formatted.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
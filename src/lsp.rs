@@ -0,0 +1,296 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! A minimal language server that only provides formatting, speaking JSON-RPC
+//! over a pair of streams (usually stdin and stdout).
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    ops::Range,
+    str,
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde_json::{Value, json};
+
+use crate::{
+    FormatError,
+    config::{Config, discover},
+    format_changed_items, format_source, output,
+};
+
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+/// The state of a running language server.
+pub struct Server<'a> {
+    /// The configuration file to use for every document instead of the ones
+    /// discovered for it.
+    config_path: Option<&'a Utf8Path>,
+    /// Whether to format every document in minimal mode, whatever its
+    /// configuration says.
+    minimal: bool,
+    /// The contents of every open document, keyed by URI.
+    documents: HashMap<String, String>,
+    shutdown_requested: bool,
+}
+
+impl<'a> Server<'a> {
+    /// A server formatting each document according to the configuration
+    /// discovered for its path, or read from `config_path` if given. The
+    /// configuration is read anew for every request, so edits to it apply
+    /// without restarting the server.
+    pub fn new(config_path: Option<&'a Utf8Path>, minimal: bool) -> Self {
+        Self {
+            config_path,
+            minimal,
+            documents: HashMap::new(),
+            shutdown_requested: false,
+        }
+    }
+
+    /// Serves requests read from `input`, writing responses to `output`, until
+    /// the client sends `exit` or closes `input`.
+    pub fn run(
+        &mut self,
+        mut input: impl BufRead,
+        mut output: impl Write,
+    ) -> io::Result<()> {
+        while let Some(message) = read_message(&mut input)? {
+            let method = message["method"].as_str().unwrap_or_default();
+            if method == "exit" {
+                break;
+            }
+
+            let response = self.handle(method, &message["params"]);
+            // notifications have no ID and get no response
+            if let Some(id) = message.get("id") {
+                let mut body = json!({ "jsonrpc": "2.0", "id": id });
+                match response {
+                    Ok(result) => body["result"] = result,
+                    Err((code, message)) => {
+                        body["error"] = json!({
+                            "code": code,
+                            "message": message,
+                        })
+                    }
+                }
+                write_message(&mut output, &body)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(
+        &mut self,
+        method: &str,
+        params: &Value,
+    ) -> Result<Value, (i64, String)> {
+        if self.shutdown_requested {
+            return Err((INVALID_REQUEST, "server is shutting down".into()));
+        }
+
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    // full document sync
+                    "textDocumentSync": 1,
+                    "documentFormattingProvider": true,
+                    "documentRangeFormattingProvider": true,
+                },
+                "serverInfo": {
+                    "name": "spadefmt",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            })),
+            "shutdown" => {
+                self.shutdown_requested = true;
+                Ok(Value::Null)
+            }
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                if let (Some(uri), Some(text)) =
+                    (document["uri"].as_str(), document["text"].as_str())
+                {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                Ok(Value::Null)
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str();
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                Ok(Value::Null)
+            }
+            "textDocument/didClose" => {
+                if let Some(uri) = params["textDocument"]["uri"].as_str() {
+                    self.documents.remove(uri);
+                }
+                Ok(Value::Null)
+            }
+            "textDocument/formatting" => self.format(params, None),
+            "textDocument/rangeFormatting" => {
+                let range = &params["range"];
+                let (Some(start), Some(end), Some(end_character)) = (
+                    range["start"]["line"].as_u64(),
+                    range["end"]["line"].as_u64(),
+                    range["end"]["character"].as_u64(),
+                ) else {
+                    return Err((INVALID_PARAMS, "missing range".into()));
+                };
+                // the end is exclusive, so a range ending at the start of a
+                // line does not touch it, but an empty range still touches
+                // the line it is on
+                let start = start as usize;
+                let end = end as usize + usize::from(end_character > 0);
+                // layout decisions depend on the surrounding code, so every
+                // top-level item the range touches is formatted as a whole,
+                // e.g., all of a `mod` for a range inside it
+                self.format(params, Some(start..end.max(start + 1)))
+            }
+            _ if method.starts_with("$/") => Ok(Value::Null),
+            _ => {
+                Err((METHOD_NOT_FOUND, format!("unsupported method {method}")))
+            }
+        }
+    }
+
+    /// Formats the document named in `params`, or only the top-level items
+    /// overlapping `lines`, a half-open range of line indices, if given. Only
+    /// top-level items are considered, so lines inside a `mod` reformat all of
+    /// it.
+    fn format(
+        &self,
+        params: &Value,
+        lines: Option<Range<usize>>,
+    ) -> Result<Value, (i64, String)> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or((INVALID_PARAMS, "missing document URI".to_string()))?;
+        let text = self
+            .documents
+            .get(uri)
+            .ok_or((INVALID_PARAMS, format!("document {uri} is not open")))?;
+
+        let formatted = self
+            .config_for(uri, text)
+            .and_then(|config| match lines {
+                Some(lines) => format_changed_items(text, &config, &[lines]),
+                None => format_source(text, &config)
+                    .map(|formatted| output::render(&formatted, text, &config)),
+            })
+            .map_err(|error| (REQUEST_FAILED, error.to_string()))?;
+
+        if &formatted == text {
+            return Ok(json!([]));
+        }
+        Ok(json!([{
+            "range": whole_document_range(text),
+            "newText": formatted,
+        }]))
+    }
+
+    /// The configuration for the document at `uri` with contents `text`. A
+    /// document that is not a file, e.g., one never saved, is configured like
+    /// a new file in the working directory.
+    fn config_for(&self, uri: &str, text: &str) -> Result<Config, FormatError> {
+        let path = file_path(uri)
+            .unwrap_or_else(|| Utf8PathBuf::from("untitled.spade"));
+        let mut config = discover::discover(&path, self.config_path)?
+            .with_directive(text)?;
+        config.minimal |= self.minimal;
+        Ok(config)
+    }
+}
+
+/// The path of the file at `uri`, if it is a `file:` URI.
+fn file_path(uri: &str) -> Option<Utf8PathBuf> {
+    let encoded = uri.strip_prefix("file://")?;
+    let mut bytes = vec![];
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = match (byte, tail.get(..2)) {
+            (b'%', Some(hex)) => str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(Utf8PathBuf::from)
+}
+
+/// The LSP range covering all of `text`, measured in UTF-16 code units as the
+/// protocol requires.
+fn whole_document_range(text: &str) -> Value {
+    let last_line_index = text.matches('\n').count();
+    let last_line = text.rsplit('\n').next().unwrap_or_default();
+    json!({
+        "start": { "line": 0, "character": 0 },
+        "end": {
+            "line": last_line_index,
+            "character": last_line.encode_utf16().count(),
+        },
+    })
+}
+
+/// Reads one `Content-Length`-framed JSON-RPC message, or `None` at the end of
+/// `input`.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            content_length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length")
+    })?;
+    let mut body = vec![0; content_length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
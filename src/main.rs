@@ -15,123 +15,725 @@
 
 use std::{
     env, fs,
-    io::{self, IsTerminal},
-    rc::Rc,
-    sync::RwLock,
+    io::{self, IsTerminal, Write},
+    ops::Range,
+    process::ExitCode,
 };
 
-use snafu::{whatever, ResultExt, Whatever};
+use camino::{Utf8Path, Utf8PathBuf};
+use rayon::prelude::*;
+use snafu::{Report, ResultExt, Whatever, whatever};
 pub use spade;
 use spade_codespan_reporting::{
-    files::{Files, SimpleFiles},
-    term::termcolor::Buffer,
+    diagnostic::Diagnostic, term::termcolor::Buffer,
 };
-use spade_diagnostics::{emitter::CodespanEmitter, CodeBundle, DiagHandler};
-use spade_parser::logos::Logos;
 use spadefmt::{
-    cli::Opts,
-    config::Config,
+    FormatError, advisory,
+    cache::{self, FormatCache},
+    cli::{
+        Emit, ExitStatus, MessageFormat, Opts, PlayOpts, PrintConfig, SwimOpts,
+    },
+    config::{self, Config, discover::ConfigCache},
     document,
-    document_builder::DocumentBuilder,
-    resolve_try_catch::{resolve_try_catch, PrintingContext},
+    error::{IoSnafu, NondeterministicSnafu, PrintSnafu},
+    explain, git,
+    highlight::{self, Theme},
+    ignore,
+    lsp::Server,
+    output,
+    play::Playground,
+    recover,
+    report::{self, DiagnosticBuffer},
+    stats::{self, FormatStats},
+    swim::{self, Project},
+    verify,
+    version::VERSION_INFO,
 };
+use tracing_subscriber::fmt::format::FmtSpan;
+
+fn main() -> ExitCode {
+    let status = run().unwrap_or_else(|error| {
+        eprintln!("Error: {}", Report::from_error(error));
+        ExitStatus::InternalError
+    });
+    ExitCode::from(status.code())
+}
+
+fn run() -> Result<ExitStatus, Whatever> {
+    if let Some(play_opts) = PlayOpts::from_env() {
+        return play(&play_opts);
+    }
+    if let Some(swim_opts) = SwimOpts::from_env() {
+        return swim(&swim_opts);
+    }
 
-#[snafu::report]
-fn main() -> Result<(), Whatever> {
     let opts = Opts::from_env();
 
     if opts.version {
         println!(
             "{} {}",
             env::args().next().expect("no program name"),
-            env!("CARGO_PKG_VERSION")
+            VERSION_INFO.version
         );
+        println!("{}", VERSION_INFO.parser());
+        if opts.verbose {
+            println!("{}", VERSION_INFO.verbose());
+        }
         println!();
         print!(include_str!("../resources/version.txt"));
 
-        return Ok(());
+        return Ok(ExitStatus::Success);
+    }
+
+    if let Some(log_level) = opts.log_level {
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(log_level)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(!opts.no_color && io::stderr().is_terminal())
+            .with_writer(io::stderr);
+        if opts.log_json {
+            subscriber.json().init();
+        } else {
+            subscriber.init();
+        }
     }
 
-    const FILE_ID: usize = 0;
+    if opts.lsp {
+        // the language server configures each document on its own
+        Server::new(opts.config_path.as_deref(), opts.minimal)
+            .run(io::stdin().lock(), io::stdout().lock())
+            .whatever_context("Language server failed")?;
+        return Ok(ExitStatus::Success);
+    }
 
-    let code = fs::read_to_string(&opts.file)
-        .whatever_context(format!("Failed to read file at {}", opts.file))?;
+    if let Some(print_config) = opts.print_config {
+        let config = match print_config {
+            PrintConfig::Default => Config::default(),
+            PrintConfig::Current => working_directory_config(&opts)?,
+        };
+        print!("{}", config.to_toml());
+        return Ok(ExitStatus::Success);
+    }
+
+    if opts.files.is_empty() {
+        whatever!("No input file given");
+    }
+
+    // files are formatted in parallel but reported in the order given
+    let configs = ConfigCache::new();
+    let files =
+        unignored_files(&opts.files, opts.config_path.as_deref(), &configs)?;
+    if opts.explain.is_some() || opts.fit {
+        return inspect_files(&opts, &configs, &files);
+    }
+    let outcomes = files
+        .par_iter()
+        .map(|file| (file, format_file(&opts, &configs, file)))
+        .collect::<Vec<_>>();
+    if opts.audit_determinism {
+        audit_determinism(&opts, &configs, &outcomes)?;
+    }
+
+    let json = opts.message_format == MessageFormat::Json;
+    let mut diagnostic_buffer = DiagnosticBuffer::new();
+    let mut report_rows = vec![];
+    let mut status = ExitStatus::Success;
+    let mut reformatted = 0;
+    let mut unparsable = 0;
+    let mut printed_header = false;
+    for (file, outcome) in outcomes {
+        let FileOutcome {
+            file,
+            code,
+            config,
+            result,
+            broken_groups,
+            already_formatted,
+            known_change,
+            warnings,
+        } = match outcome {
+            Ok(outcome) => outcome,
+            Err(error) if json => {
+                report::emit_error_json(file, "", &error, &mut io::stderr())
+                    .whatever_context("Failed to report errors")?;
+                status = status.max(ExitStatus::InternalError);
+                continue;
+            }
+            Err(error) => {
+                return Err(error).whatever_context("Failed to load input");
+            }
+        };
+
+        match result {
+            Ok(_) if known_change.is_some() => {
+                reformatted += 1;
+                if json {
+                    report::emit_unformatted_json(
+                        &file,
+                        &code,
+                        known_change,
+                        &mut io::stdout(),
+                    )
+                    .whatever_context("Failed to report differences")?;
+                } else {
+                    println!("{file}");
+                }
+                status = status.max(ExitStatus::Different);
+            }
+            Ok(formatted) => {
+                // with a diff base, untouched regions keep their exact bytes
+                let output = if opts.diff_base.is_some() || already_formatted {
+                    formatted
+                } else {
+                    output::render(&formatted, &code, &config)
+                };
+                let found_formatted = !already_formatted && output == code;
+                if let Some(cache) =
+                    format_cache(&opts).filter(|_| found_formatted)
+                {
+                    cache
+                        .mark_formatted(&code, &config)
+                        .whatever_context("Failed to update cache")?;
+                }
+                if output != code {
+                    reformatted += 1;
+                }
+                // only the path matters, so skip everything else about it
+                if opts.list_different {
+                    if output != code {
+                        if json {
+                            report::emit_unformatted_json(
+                                &file,
+                                &code,
+                                output::changed_range(&code, &output),
+                                &mut io::stdout(),
+                            )
+                            .whatever_context("Failed to report differences")?;
+                        } else {
+                            println!("{file}");
+                        }
+                        status = status.max(ExitStatus::Different);
+                    }
+                    continue;
+                }
+                if opts.report {
+                    report_rows.push((
+                        file.clone(),
+                        FormatStats::new(&code, &output, broken_groups),
+                    ));
+                }
+                // like `head`, several files are each introduced by a header
+                if opts.emit == Emit::Stdout && files.len() > 1 {
+                    if printed_header {
+                        println!();
+                    }
+                    println!("==> {file} <==");
+                    printed_header = true;
+                }
+                match opts.emit {
+                    Emit::Stdout if opts.color_output => {
+                        let theme = match &config.theme {
+                            Some(path) => Theme::from_path(Utf8Path::new(path))
+                                .whatever_context("Failed to load theme")?,
+                            None => Theme::default(),
+                        };
+                        let mut buffer = Buffer::ansi();
+                        highlight::highlight(&output, &theme, &mut buffer)
+                            .and_then(|()| {
+                                io::stdout().write_all(buffer.as_slice())
+                            })
+                            .whatever_context("Failed to print output")?;
+                    }
+                    Emit::Stdout => print!("{output}"),
+                    Emit::Hash => println!(
+                        "{:016x}  {}",
+                        spadefmt::content_hash(&output),
+                        file
+                    ),
+                }
+
+                if !warnings.is_empty() {
+                    advisory::emit(
+                        file.as_str(),
+                        &code,
+                        &warnings,
+                        &mut io::stderr(),
+                        !opts.no_color && io::stderr().is_terminal(),
+                    )
+                    .whatever_context("Failed to report warnings")?;
+                }
+
+                if opts.advise {
+                    let notes = advisory::advise(&code, &config)
+                        .whatever_context("Failed to compute advice")?;
+                    advisory::emit(
+                        file.as_str(),
+                        &code,
+                        &notes,
+                        &mut io::stderr(),
+                        !opts.no_color && io::stderr().is_terminal(),
+                    )
+                    .whatever_context("Failed to report advice")?;
+                }
+            }
+            Err(FormatError::Parse { .. }) => {
+                diagnostic_buffer.add_parse_errors(file, code);
+                unparsable += 1;
+            }
+            Err(error) if json => {
+                report::emit_error_json(
+                    &file,
+                    &code,
+                    &error,
+                    &mut io::stderr(),
+                )
+                .whatever_context("Failed to report errors")?;
+                status = status.max(ExitStatus::InternalError);
+            }
+            Err(error) => {
+                return Err(error).with_whatever_context(|_| {
+                    format!("Failed to format {file}")
+                });
+            }
+        }
+    }
+
+    if opts.report {
+        match opts.report_format {
+            MessageFormat::Human => {
+                stats::emit_table(&report_rows, &mut io::stderr())
+            }
+            MessageFormat::Json => {
+                stats::emit_json(&report_rows, &mut io::stderr())
+            }
+        }
+        .whatever_context("Failed to print report")?;
+    }
 
-    let mut files = SimpleFiles::new();
-    let file_id = files.add(opts.file.to_string(), code.clone());
+    if let Some(cache) = format_cache(&opts) {
+        cache
+            .evict(cache::MAX_ENTRIES)
+            .whatever_context("Failed to evict from cache")?;
+    }
 
-    let diagnostic_handler = DiagHandler::new(Box::new(CodespanEmitter));
+    if emit_parse_errors(&opts, diagnostic_buffer)? {
+        status = status.max(ExitStatus::ParseError);
+    }
+    if opts.summary {
+        eprintln!(
+            "{} checked, {reformatted} would be reformatted, {unparsable} \
+             failed to parse",
+            plural(files.len(), "file")
+        );
+    }
+    Ok(status)
+}
 
-    let code_bundle = Rc::new(RwLock::new(CodeBundle { files }));
+/// Formats each file of `parallel`, the outcomes of formatting files in
+/// parallel, again one at a time, failing unless every file was formatted to
+/// the same output or failed the same way both times.
+fn audit_determinism(
+    opts: &Opts,
+    configs: &ConfigCache,
+    parallel: &[(&Utf8PathBuf, Result<FileOutcome, FormatError>)],
+) -> Result<(), Whatever> {
+    let serial = parallel
+        .iter()
+        .map(|(file, _)| format_file(opts, configs, file))
+        .collect::<Vec<_>>();
 
-    let mut buffer = if opts.no_color || !io::stderr().is_terminal() {
-        Buffer::no_color()
+    for ((file, parallel), serial) in parallel.iter().zip(&serial) {
+        match (formatted_code(serial), formatted_code(parallel)) {
+            (Ok(serial), Ok(parallel)) if serial == parallel => {}
+            (Ok(_), Ok(_)) => {
+                return NondeterministicSnafu { path: *file }
+                    .fail()
+                    .whatever_context("Determinism audit failed");
+            }
+            (Ok(_), Err(error)) => {
+                whatever!("Formatting {file} failed only in parallel: {error}")
+            }
+            (Err(error), Ok(_)) => {
+                whatever!("Formatting {file} failed only serially: {error}")
+            }
+            // reported along with the formatted files
+            (Err(serial), Err(parallel)) if serial == parallel => {}
+            (Err(serial), Err(parallel)) => whatever!(
+                "Formatting {file} failed differently serially and in \
+                 parallel: {serial}; {parallel}"
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// The formatted code in `outcome`, or the error formatting or reading the
+/// file failed with.
+fn formatted_code(
+    outcome: &Result<FileOutcome, FormatError>,
+) -> Result<&str, String> {
+    match outcome {
+        Ok(outcome) => outcome.result.as_deref().map_err(ToString::to_string),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// Runs a mode that prints something about each of `files` instead of
+/// formatting it, i.e., `--explain` or `--fit`, in the order given.
+fn inspect_files(
+    opts: &Opts,
+    configs: &ConfigCache,
+    files: &[Utf8PathBuf],
+) -> Result<ExitStatus, Whatever> {
+    let outcomes = files
+        .par_iter()
+        .map(|file| {
+            let (code, config) = read_file(opts, configs, file)?;
+            let result = match opts.explain {
+                Some(position) => explain::explain(&code, &config, position),
+                None => spadefmt::fit_width(&code, &config).map(|max_width| {
+                    format!("{file}: max_width = {max_width}")
+                }),
+            };
+            Ok((file, code, result))
+        })
+        .collect::<Vec<Result<_, FormatError>>>();
+
+    let mut diagnostic_buffer = DiagnosticBuffer::new();
+    for outcome in outcomes {
+        let (file, code, result) =
+            outcome.whatever_context("Failed to load input")?;
+        match result {
+            Ok(text) => println!("{text}"),
+            Err(FormatError::Parse { .. }) => {
+                diagnostic_buffer.add_parse_errors(file, code);
+            }
+            Err(error) => {
+                return Err(error).with_whatever_context(|_| {
+                    format!("Failed to inspect {file}")
+                });
+            }
+        }
+    }
+
+    Ok(if emit_parse_errors(opts, diagnostic_buffer)? {
+        ExitStatus::ParseError
     } else {
-        Buffer::ansi()
-    };
+        ExitStatus::Success
+    })
+}
+
+/// Reports the parse errors in `diagnostic_buffer` in the format `opts` ask
+/// for, returning whether there were any.
+fn emit_parse_errors(
+    opts: &Opts,
+    diagnostic_buffer: DiagnosticBuffer,
+) -> Result<bool, Whatever> {
+    if diagnostic_buffer.is_empty() {
+        return Ok(false);
+    }
+    if opts.message_format == MessageFormat::Json {
+        diagnostic_buffer.emit_json(&mut io::stderr())
+    } else {
+        diagnostic_buffer.emit(
+            &mut io::stderr(),
+            !opts.no_color && io::stderr().is_terminal(),
+        )
+    }
+    .whatever_context("Failed to report errors")?;
+    Ok(true)
+}
+
+/// `count` followed by `noun`, made plural unless `count` is one.
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{count} {noun}")
+    } else {
+        format!("{count} {noun}s")
+    }
+}
 
-    let mut error_handler = spade::error_handling::ErrorHandler::new(
-        &mut buffer,
-        diagnostic_handler,
-        code_bundle.clone(),
-    );
-
-    let mut parser = spade_parser::Parser::new(
-        spade_parser::lexer::TokenKind::lexer(&code),
-        FILE_ID,
-    );
-
-    let root = match parser.top_level_module_body() {
-        Ok(root) => root,
-        Err(error) => {
-            error_handler.report(&error);
-            for error in &parser.diags.errors {
-                error_handler.report(error);
-            }
-            whatever!("Exiting due to errors")
+/// The configuration for a new file in the working directory according to
+/// `opts`.
+fn working_directory_config(opts: &Opts) -> Result<Config, Whatever> {
+    let mut config = config::discover::discover(
+        Utf8Path::new("untitled.spade"),
+        opts.config_path.as_deref(),
+    )
+    .whatever_context("Failed to load config")?;
+    config.minimal |= opts.minimal;
+    Ok(config)
+}
+
+/// `files` without those skipped by an ignore file or the configuration.
+fn unignored_files(
+    files: &[Utf8PathBuf],
+    config_path: Option<&Utf8Path>,
+    configs: &ConfigCache,
+) -> Result<Vec<Utf8PathBuf>, Whatever> {
+    let mut unignored = vec![];
+    for file in files {
+        if !ignore::is_ignored(file, config_path, configs)
+            .whatever_context("Failed to read ignore rules")?
+        {
+            unignored.push(file.clone());
         }
+    }
+    Ok(unignored)
+}
+
+/// A file along with the configuration it was formatted with and the result.
+struct FileOutcome {
+    file: Utf8PathBuf,
+    code: String,
+    config: Config,
+    result: Result<String, FormatError>,
+    /// How many groups had to be broken, if the formatting mode counts them.
+    broken_groups: Option<usize>,
+    /// Whether the cache or a check for differences says the file is formatted
+    /// already, in which case `result` is its contents as they are.
+    already_formatted: bool,
+    /// The byte range of `code` found to differ from its formatted code
+    /// before all of it was formatted, in which case `result` is empty.
+    known_change: Option<Range<usize>>,
+    /// Warnings about formatting the file, labeling spans of `code`.
+    warnings: Vec<Diagnostic<()>>,
+}
+
+/// The cache of formatted files to use, if any. Only plain formatting is
+/// cached: a marker does not record the mode that checked the file, and
+/// `--recover` or `--verify` can reach a different verdict than a plain run.
+fn format_cache(opts: &Opts) -> Option<FormatCache> {
+    opts.cache_dir
+        .clone()
+        .filter(|_| is_plain(opts) && !opts.no_cache)
+        .map(FormatCache::new)
+}
+
+/// Whether `opts` ask for plain formatting, with no other mode or check.
+fn is_plain(opts: &Opts) -> bool {
+    !opts.debug
+        && opts.explain.is_none()
+        && !opts.fit
+        && !opts.audit_determinism
+        && !opts.paranoid
+        && !opts.recover
+        && !opts.verify
+        && opts.diff_base.is_none()
+}
+
+/// Reads `file` along with the configuration that applies to it according to
+/// `opts`, found through `configs`.
+fn read_file(
+    opts: &Opts,
+    configs: &ConfigCache,
+    file: &Utf8Path,
+) -> Result<(String, Config), FormatError> {
+    let code = fs::read_to_string(file).context(IoSnafu { path: file })?;
+    let mut config = configs
+        .discover(file, opts.config_path.as_deref())?
+        .with_directive(&code)?;
+    config.minimal |= opts.minimal;
+    Ok((code, config))
+}
+
+/// Reads and formats `file` according to `opts` and the configuration that
+/// applies to it, found through `configs`. Only failing to read the file or
+/// its configuration is an error here; formatting errors are part of the
+/// outcome.
+fn format_file(
+    opts: &Opts,
+    configs: &ConfigCache,
+    file: &Utf8Path,
+) -> Result<FileOutcome, FormatError> {
+    let (code, config) = read_file(opts, configs, file)?;
+
+    if format_cache(opts)
+        .is_some_and(|cache| cache.is_formatted(&code, &config))
+    {
+        return Ok(FileOutcome {
+            file: file.to_path_buf(),
+            result: Ok(code.clone()),
+            code,
+            config,
+            broken_groups: None,
+            already_formatted: true,
+            known_change: None,
+            warnings: vec![],
+        });
+    }
+
+    // only whether the file differs matters, which the first differing item
+    // settles
+    if opts.list_different && is_plain(opts) {
+        let result = spadefmt::formatting_changes(&code, &config);
+        let known_change = result.as_ref().ok().cloned().flatten();
+        return Ok(FileOutcome {
+            file: file.to_path_buf(),
+            result: result.map(|changes| match changes {
+                Some(_) => String::new(),
+                None => code.clone(),
+            }),
+            code,
+            config,
+            broken_groups: None,
+            already_formatted: known_change.is_none(),
+            known_change,
+            warnings: vec![],
+        });
+    }
+
+    let mut broken_groups = None;
+    let mut warnings = vec![];
+    let result = if opts.debug {
+        // the debug printer recurses as deep as the document nests
+        spadefmt::with_deep_stack(|| {
+            let (document_store, root_idx) =
+                spadefmt::build_document(&code, &config)?;
+            let mut buffer = String::new();
+            let mut f = inform::fmt::IndentWriter::new(
+                &mut buffer,
+                config.indent.inner,
+            );
+            document::debug_print(&document_store, &mut f, root_idx)
+                .context(PrintSnafu)?;
+            Ok(buffer)
+        })
+    } else if opts.paranoid {
+        spadefmt::audit_item_indentation(&code, &config)
+            .and_then(|()| spadefmt::format_source(&code, &config))
+    } else if let Some(base) = &opts.diff_base {
+        git::changed_lines(file, base).and_then(|changed_lines| {
+            spadefmt::format_changed_items(&code, &config, &changed_lines)
+        })
+    } else if opts.recover {
+        recover::format_recovering(&code, &config).map(
+            |(formatted, recovery_warnings)| {
+                warnings = recovery_warnings;
+                formatted
+            },
+        )
+    } else {
+        spadefmt::format_source_counting_breaks(&code, &config).map(
+            |(formatted, breaks)| {
+                broken_groups = Some(breaks);
+                formatted
+            },
+        )
     };
+    // code left as written does not parse, so there is nothing to compare
+    let result = if opts.verify && !opts.debug && warnings.is_empty() {
+        result.and_then(|formatted| {
+            verify::verify(&code, &formatted).map(|()| formatted)
+        })
+    } else {
+        result
+    };
+
+    Ok(FileOutcome {
+        file: file.to_path_buf(),
+        code,
+        config,
+        result,
+        broken_groups,
+        already_formatted: false,
+        known_change: None,
+        warnings,
+    })
+}
+
+fn play(opts: &PlayOpts) -> Result<ExitStatus, Whatever> {
+    let code = fs::read_to_string(&opts.file)
+        .context(IoSnafu { path: &opts.file })
+        .whatever_context("Failed to read input file")?;
+    let config = config::discover::discover(&opts.file, None)
+        .and_then(|config| config.with_directive(&code))
+        .whatever_context("Failed to load config")?;
 
-    let test_config_contents = fs::read_to_string("spadefmt.toml")
-        .whatever_context("test file spadefmt.toml should be there")?;
-    let test_config = toml::from_str::<Config>(&test_config_contents)
-        .whatever_context("Failed to decode config")?;
+    match Playground::new(&code, &config) {
+        Ok(mut playground) => playground
+            .run()
+            .map(|()| ExitStatus::Success)
+            .whatever_context("Failed to run playground"),
+        Err(FormatError::Parse { .. }) => {
+            let mut diagnostic_buffer = DiagnosticBuffer::new();
+            diagnostic_buffer.add_parse_errors(&opts.file, code);
+            diagnostic_buffer
+                .emit(&mut io::stderr(), io::stderr().is_terminal())
+                .whatever_context("Failed to report errors")?;
+            Ok(ExitStatus::ParseError)
+        }
+        Err(error) => Err(error).whatever_context("Failed to format file"),
+    }
+}
 
-    let indent = test_config.indent.inner;
+fn swim(swim_opts: &SwimOpts) -> Result<ExitStatus, Whatever> {
+    let manifest_path = match &swim_opts.manifest_path {
+        Some(manifest_path) => manifest_path.clone(),
+        None => match swim::find_manifest(Utf8Path::new(".")) {
+            Some(manifest_path) => manifest_path,
+            None => whatever!(
+                "No {} found in the working directory or its ancestors",
+                swim::MANIFEST_FILE_NAME
+            ),
+        },
+    };
+    let project = Project::from_manifest(&manifest_path)
+        .whatever_context("Failed to load project")?;
 
-    let (mut document_store, root_idx) = {
-        let code_bundle_guard = code_bundle.read().unwrap();
-        let file = code_bundle_guard.files.get(file_id).unwrap();
-        DocumentBuilder::new(test_config.indent.inner as isize)
-            .build_root(&root, file)
+    let configs = ConfigCache::new();
+    let opts = Opts {
+        config_path: swim_opts.config_path.clone(),
+        files: unignored_files(
+            &project.files,
+            swim_opts.config_path.as_deref(),
+            &configs,
+        )?,
+        ..Default::default()
     };
+    let outcomes = opts
+        .files
+        .par_iter()
+        .map(|file| format_file(&opts, &configs, file))
+        .collect::<Vec<_>>();
 
-    if opts.debug {
-        let mut buffer = String::new();
-        let mut f = inform::fmt::IndentWriter::new(&mut buffer, indent);
-        document::debug_print(&document_store, &mut f, root_idx)
-            .whatever_context("Failed to print document")?;
-        println!("{buffer}");
-        return Ok(());
-    }
-
-    let new_root_idx = resolve_try_catch(
-        &mut document_store,
-        root_idx,
-        &mut PrintingContext::new(test_config.max_width.inner),
-    );
-
-    let mut buffer = String::new();
-    let mut f = inform::fmt::IndentWriter::new(&mut buffer, indent);
-    document::print_resolved(
-        &document_store,
-        &mut f,
-        new_root_idx,
-        false,
-        &mut false,
-    )
-    .whatever_context("Failed to print document")?;
-    println!("{buffer}");
+    let mut diagnostic_buffer = DiagnosticBuffer::new();
+    for outcome in outcomes {
+        let FileOutcome {
+            file,
+            code,
+            config,
+            result,
+            ..
+        } = outcome.whatever_context("Failed to load input")?;
 
-    Ok(())
+        match result {
+            Ok(formatted) => {
+                let output = output::render(&formatted, &code, &config);
+                // leave untouched files alone so their timestamps do not
+                // trigger rebuilds
+                if output != code {
+                    fs::write(&file, output)
+                        .context(IoSnafu { path: &file })
+                        .whatever_context("Failed to write formatted file")?;
+                }
+            }
+            Err(FormatError::Parse { .. }) => {
+                diagnostic_buffer.add_parse_errors(file, code);
+            }
+            Err(error) => {
+                return Err(error).with_whatever_context(|_| {
+                    format!("Failed to format {file}")
+                });
+            }
+        }
+    }
+
+    if !diagnostic_buffer.is_empty() {
+        diagnostic_buffer
+            .emit(&mut io::stderr(), io::stderr().is_terminal())
+            .whatever_context("Failed to report errors")?;
+        return Ok(ExitStatus::ParseError);
+    }
+    Ok(ExitStatus::Success)
 }
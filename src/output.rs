@@ -0,0 +1,67 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Turning formatted code into the exact bytes written out, i.e., applying
+//! line ending options.
+
+use std::ops::Range;
+
+use crate::config::{Config, NewlineStyle};
+
+/// The line ending `style` stands for when formatting `source`.
+pub fn line_ending(style: NewlineStyle, source: &str) -> &'static str {
+    match style {
+        NewlineStyle::Unix => "\n",
+        NewlineStyle::Windows => "\r\n",
+        NewlineStyle::Native if cfg!(windows) => "\r\n",
+        NewlineStyle::Native => "\n",
+        NewlineStyle::Auto => match source.find('\n') {
+            Some(index) if source[..index].ends_with('\r') => "\r\n",
+            _ => "\n",
+        },
+    }
+}
+
+/// Renders `formatted`, the output of [`crate::format_source`] for `source`,
+/// with the line endings and final newline `config` asks for.
+pub fn render(formatted: &str, source: &str, config: &Config) -> String {
+    let line_ending = line_ending(config.newline_style, source);
+    let mut output = formatted.replace('\n', line_ending);
+    if config.ensure_final_newline || source.ends_with('\n') {
+        output.push_str(line_ending);
+    }
+    output
+}
+
+/// The byte range of `source` that differs from `rendered`, its rendered
+/// formatted code, between their longest common prefix and suffix, or `None`
+/// if they are equal.
+pub fn changed_range(source: &str, rendered: &str) -> Option<Range<usize>> {
+    if source == rendered {
+        return None;
+    }
+    let prefix: usize = source
+        .chars()
+        .zip(rendered.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum();
+    let suffix: usize = source[prefix..]
+        .chars()
+        .rev()
+        .zip(rendered[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum();
+    Some(prefix..source.len() - suffix)
+}
@@ -0,0 +1,181 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! An interactive terminal view for trying out `max_width` values on a file.
+
+use std::io::{self, Write};
+
+use crossterm::{
+    cursor, event,
+    event::{Event, KeyCode, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal,
+};
+use snafu::ResultExt;
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    FormatError, build_document,
+    config::Config,
+    document::{DocumentIdx, InternedDocumentStore},
+    error::TerminalSnafu,
+    resolve_and_print,
+};
+
+/// Puts the terminal into raw mode on an alternate screen for as long as it
+/// lives, restoring it even on panic.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new(output: &mut impl Write) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        queue!(output, terminal::EnterAlternateScreen, cursor::Hide)?;
+        output.flush()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = queue!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = stdout.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// A file whose document is built once and re-resolved for each width.
+pub struct Playground {
+    config: Config,
+    document_store: InternedDocumentStore,
+    root_idx: DocumentIdx,
+    /// The first formatted line shown.
+    scroll: usize,
+}
+
+impl Playground {
+    pub fn new(source: &str, config: &Config) -> Result<Self, FormatError> {
+        let (document_store, root_idx) = build_document(source, config)?;
+        Ok(Self {
+            config: config.clone(),
+            document_store,
+            root_idx,
+            scroll: 0,
+        })
+    }
+
+    /// Formats the file at the current `max_width`.
+    pub fn format(&mut self) -> Result<String, FormatError> {
+        let formatted = resolve_and_print(
+            &mut self.document_store,
+            self.root_idx,
+            &self.config,
+        )?;
+        // only the unresolved document is needed for the next width
+        self.root_idx = self.document_store.compact(&[self.root_idx])[0];
+        Ok(formatted)
+    }
+
+    /// Runs the interactive view on stdout until the user quits.
+    pub fn run(&mut self) -> Result<(), FormatError> {
+        let mut stdout = io::stdout();
+        let _guard = TerminalGuard::new(&mut stdout).context(TerminalSnafu)?;
+
+        loop {
+            let formatted = self.format()?;
+            self.render(&mut stdout, &formatted)
+                .context(TerminalSnafu)?;
+
+            let Event::Key(key) = event::read().context(TerminalSnafu)? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            let max_width = &mut self.config.max_width.inner;
+            match key.code {
+                KeyCode::Char('+' | '=') => *max_width += 1,
+                KeyCode::Char('-') => *max_width = (*max_width - 1).max(1),
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.scroll = self.scroll.saturating_sub(1)
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.scroll = (self.scroll + 1)
+                        .min(formatted.lines().count().saturating_sub(1))
+                }
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                KeyCode::Char('c')
+                    if key.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Draws a status line and then as much of `formatted` as fits, with the
+    /// part of each line past `max_width` shown in reverse video.
+    fn render(
+        &self,
+        output: &mut impl Write,
+        formatted: &str,
+    ) -> io::Result<()> {
+        let (_, rows) = terminal::size()?;
+        let max_width = self.config.max_width.inner;
+
+        queue!(
+            output,
+            terminal::Clear(terminal::ClearType::All),
+            cursor::MoveTo(0, 0),
+            SetAttribute(Attribute::Bold),
+            Print(format!(
+                "max_width = {max_width}  (+/- to change, up/down to scroll, \
+                 q to quit)"
+            )),
+            SetAttribute(Attribute::Reset),
+        )?;
+
+        for (row, line) in formatted
+            .lines()
+            .skip(self.scroll)
+            .take(usize::from(rows).saturating_sub(1))
+            .enumerate()
+        {
+            // split where the display width first exceeds `max_width`, so
+            // wide characters are highlighted the same way they are measured
+            let mut width = 0;
+            let split = line
+                .char_indices()
+                .find(|(_, c)| {
+                    width += c.width().unwrap_or(0);
+                    width > max_width
+                })
+                .map_or(line.len(), |(index, _)| index);
+            let (fitting, overflowing) = line.split_at(split);
+            queue!(
+                output,
+                cursor::MoveTo(0, row as u16 + 1),
+                Print(fitting),
+                SetAttribute(Attribute::Reverse),
+                Print(overflowing),
+                SetAttribute(Attribute::Reset),
+            )?;
+        }
+
+        output.flush()
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Formatting the top-level items of a file with syntax errors that do parse,
+//! keeping the rest as written.
+
+use std::ops::Range;
+
+use spade_codespan_reporting::diagnostic::{Diagnostic, Label};
+use spade_parser::{lexer::TokenKind, logos::Logos};
+
+use crate::{FormatError, config::Config, format_source, parse};
+
+/// Formats `source` like [`format_source`], except that if it does not parse,
+/// each top-level item that does not parse on its own is copied as written
+/// and the rest are formatted. Also returns a warning labeling each item
+/// copied as written.
+///
+/// Fails with the original parse error if no item parses.
+pub fn format_recovering(
+    source: &str,
+    config: &Config,
+) -> Result<(String, Vec<Diagnostic<()>>), FormatError> {
+    let error = match format_source(source, config) {
+        Err(error) if error.is_parse_error() => error,
+        result => return result.map(|formatted| (formatted, vec![])),
+    };
+
+    let chunks = top_level_chunks(source)
+        .into_iter()
+        .map(|chunk| {
+            let parses = parse(&source[chunk.clone()]).is_ok();
+            (chunk, parses)
+        })
+        .collect::<Vec<_>>();
+    if !chunks.iter().any(|(_, parses)| *parses) {
+        return Err(error);
+    }
+    tracing::debug!(
+        failed = chunks.iter().filter(|(_, parses)| !parses).count(),
+        "recovering from parse errors"
+    );
+
+    let mut pieces = vec![];
+    let mut warnings = vec![];
+    let mut index = 0;
+    while index < chunks.len() {
+        let (chunk, parses) = &chunks[index];
+        if !parses {
+            let text = &source[chunk.clone()];
+            let leading = text.len() - text.trim_start().len();
+            let trimmed = text.trim();
+            pieces.push(trimmed.to_string());
+            warnings.push(
+                Diagnostic::warning()
+                    .with_message("could not parse this item")
+                    .with_labels(vec![Label::primary(
+                        (),
+                        chunk.start + leading
+                            ..chunk.start + leading + trimmed.len(),
+                    )])
+                    .with_notes(vec!["it was left as written".into()]),
+            );
+            index += 1;
+            continue;
+        }
+
+        // format each run of items that parse together, so blank lines and
+        // imports between them are handled as usual
+        let run_end = chunks[index..]
+            .iter()
+            .position(|(_, parses)| !parses)
+            .map_or(chunks.len(), |offset| index + offset);
+        let run = chunk.start..chunks[run_end - 1].0.end;
+        let formatted = format_source(&source[run], config)?;
+        pieces.push(formatted.trim_end().to_string());
+        index = run_end;
+    }
+
+    // like `format_source`, without the final newline, which the caller adds
+    Ok((pieces.join("\n\n"), warnings))
+}
+
+/// Splits `source` into byte ranges that each hold one top-level item, by
+/// ending a range at every `;` or `}` outside of braces. Text after the last
+/// such token forms a range of its own unless it is only whitespace.
+fn top_level_chunks(source: &str) -> Vec<Range<usize>> {
+    let mut chunks = vec![];
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut lexer = TokenKind::lexer(source);
+    while lexer.next().is_some() {
+        let ends_item = match lexer.slice() {
+            "{" => {
+                depth += 1;
+                false
+            }
+            "}" => {
+                depth = depth.saturating_sub(1);
+                depth == 0
+            }
+            ";" => depth == 0,
+            _ => false,
+        };
+        if ends_item {
+            let end = lexer.span().end;
+            chunks.push(start..end);
+            start = end;
+        }
+    }
+    if !source[start..].trim().is_empty() {
+        chunks.push(start..source.len());
+    }
+    chunks
+}
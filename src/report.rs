@@ -0,0 +1,207 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    error::Error,
+    io::{self, Write},
+    ops::Range,
+    rc::Rc,
+    sync::RwLock,
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde_json::{Value, json};
+use spade_codespan_reporting::{files::SimpleFiles, term::termcolor::Buffer};
+use spade_diagnostics::{
+    CodeBundle, DiagHandler, Diagnostic, emitter::CodespanEmitter,
+};
+
+use crate::{FILE_ID, FormatError, parse_file};
+
+/// Diagnostics for a single file, along with the code they point into.
+struct FileDiagnostics {
+    path: Utf8PathBuf,
+    code: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Collects diagnostics instead of streaming them as they are encountered, so
+/// that they can be emitted in an order that does not depend on the order in
+/// which files were processed.
+///
+/// Every file is registered under its own file ID in one code bundle shared
+/// across the run, so diagnostics can point into any of them.
+pub struct DiagnosticBuffer {
+    code_bundle: Rc<RwLock<CodeBundle>>,
+    files: Vec<FileDiagnostics>,
+}
+
+impl Default for DiagnosticBuffer {
+    fn default() -> Self {
+        Self {
+            code_bundle: Rc::new(RwLock::new(CodeBundle {
+                files: SimpleFiles::new(),
+            })),
+            files: vec![],
+        }
+    }
+}
+
+impl DiagnosticBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the parse errors of `code`, the contents of the file at `path`.
+    ///
+    /// The file is registered in the code bundle and parsed again under its
+    /// file ID there, since formatting always parses under [`FILE_ID`].
+    pub fn add_parse_errors(
+        &mut self,
+        path: impl Into<Utf8PathBuf>,
+        code: String,
+    ) {
+        let path = path.into();
+        let file_id = self
+            .code_bundle
+            .write()
+            .expect("code bundle lock was poisoned")
+            .files
+            .add(path.to_string(), code.clone());
+        let diagnostics = match parse_file(&code, file_id) {
+            Err(FormatError::Parse { diagnostics }) => diagnostics,
+            _ => vec![],
+        };
+        self.files.push(FileDiagnostics {
+            path,
+            code,
+            diagnostics,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.iter().all(|file| file.diagnostics.is_empty())
+    }
+
+    /// Renders every buffered diagnostic to `output`, grouped by file and
+    /// ordered by file path. Diagnostics for the same file keep the order in
+    /// which they were reported.
+    pub fn emit(
+        mut self,
+        output: &mut impl Write,
+        color: bool,
+    ) -> io::Result<()> {
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut buffer = if color {
+            Buffer::ansi()
+        } else {
+            Buffer::no_color()
+        };
+
+        let mut error_handler = spade::error_handling::ErrorHandler::new(
+            &mut buffer,
+            DiagHandler::new(Box::new(CodespanEmitter)),
+            self.code_bundle,
+        );
+        for diagnostic in self.files.iter().flat_map(|file| &file.diagnostics) {
+            error_handler.report(diagnostic);
+        }
+
+        output.write_all(buffer.as_slice())
+    }
+
+    /// Writes every buffered diagnostic to `output` as a line-delimited JSON
+    /// record (see [`json_record`]), in the same order as [`Self::emit`].
+    pub fn emit_json(mut self, output: &mut impl Write) -> io::Result<()> {
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        for file in &self.files {
+            for diagnostic in &file.diagnostics {
+                let (span, _) = diagnostic.labels.span;
+                let record = json_record(
+                    &file.path,
+                    &file.code,
+                    Some(span.start().to_usize()..span.end().to_usize()),
+                    diagnostic.labels.message.as_str(),
+                );
+                writeln!(output, "{record}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes `error`, which stopped the file at `path` with contents `code` from
+/// being formatted, to `output` as a line-delimited JSON record (see
+/// [`json_record`]). The message includes the chain of underlying causes.
+pub fn emit_error_json(
+    path: &Utf8Path,
+    code: &str,
+    error: &dyn Error,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    writeln!(output, "{}", json_record(path, code, None, &message))
+}
+
+/// Writes a line-delimited JSON record (see [`json_record`]) to `output` saying
+/// that the file at `path` with contents `code` would be reformatted, pointing
+/// at the byte range `span` of `code` that formatting changes.
+pub fn emit_unformatted_json(
+    path: &Utf8Path,
+    code: &str,
+    span: Option<Range<usize>>,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(
+        output,
+        "{}",
+        json_record(path, code, span, "would be reformatted")
+    )
+}
+
+/// A machine-readable record of an error with `message` in the file at `path`
+/// with contents `code`, optionally pointing at the byte range `span` of
+/// `code`. Span endpoints carry both the byte offset and the one-based line
+/// and column, counted in characters.
+pub fn json_record(
+    path: &Utf8Path,
+    code: &str,
+    span: Option<Range<usize>>,
+    message: &str,
+) -> Value {
+    let position = |offset: usize| {
+        let before = &code[..offset.min(code.len())];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        json!({
+            "offset": offset,
+            "line": before.matches('\n').count() + 1,
+            "column": before[line_start..].chars().count() + 1,
+        })
+    };
+    json!({
+        "file": path,
+        "level": "error",
+        "span": span.map(|span| json!({
+            "start": position(span.start),
+            "end": position(span.end),
+        })),
+        "message": message,
+    })
+}
@@ -11,7 +11,43 @@
 // details. You should have received a copy of the GNU General Public License
 // along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::document::{Document, DocumentIdx, InternedDocumentStore};
+use std::{collections::HashMap, ops::Range};
+
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    config::{Config, Overflow},
+    document::{Document, DocumentIdx, InternedDocumentStore},
+};
+
+/// How resolution decided a [`Document::TryCatch`] that made it into the
+/// resolved document.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Choice {
+    /// The decided document.
+    pub idx: DocumentIdx,
+    /// The byte range of the innermost construct of the source the document
+    /// was built for, if known. See [`Document::Spanned`].
+    pub span: Option<Range<usize>>,
+    /// The column the document started at.
+    pub start_column: usize,
+    /// The column the try body ended at.
+    pub end_column: usize,
+    /// The maximum width in effect.
+    pub max_width: usize,
+    /// Whether the try body was kept, rather than the catch body.
+    pub kept_try: bool,
+}
+
+/// Text that overflowed the maximum width even though it started a line, so
+/// that no choice of layout could have made it fit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct UnavoidableOverflow {
+    /// The zero-based line of the output it is on.
+    pub line: usize,
+    /// The maximum width in effect.
+    pub max_width: usize,
+}
 
 #[derive(Default, Clone, Debug)]
 pub struct PrintingContext {
@@ -22,6 +58,21 @@ pub struct PrintingContext {
     flatten: bool,
     trying: bool,
     tainted: bool,
+    backtracks: usize,
+    /// The zero-based line of the output the next text will be printed on.
+    line: usize,
+    /// Whether the current line started with an unavoidable overflow.
+    line_overflowed: bool,
+    /// Whether overflowing a line that starts with an unavoidable overflow
+    /// taints the context like any other overflow.
+    allow_unavoidable_overflow: bool,
+    unavoidable_overflows: Vec<UnavoidableOverflow>,
+    /// If recording, the choices made so far. Choices made in a try body that
+    /// is backtracked out of are forgotten along with the rest of its context.
+    choices: Option<Vec<Choice>>,
+    /// If recording choices, the spans of the constructs being resolved,
+    /// innermost last.
+    spans: Vec<Range<usize>>,
 }
 
 impl PrintingContext {
@@ -32,116 +83,405 @@ impl PrintingContext {
         }
     }
 
+    /// A context for resolving to fit `config`.
+    pub fn for_config(config: &Config) -> Self {
+        Self {
+            allow_unavoidable_overflow: config.overflow == Overflow::Allow,
+            ..Self::new(config.max_width.inner)
+        }
+    }
+
+    /// Records every choice made from now on; see [`Self::choices`].
+    pub fn recording_choices(mut self) -> Self {
+        self.choices = Some(vec![]);
+        self
+    }
+
+    /// The choices made, in order, if recording.
+    pub fn choices(&self) -> Option<&[Choice]> {
+        self.choices.as_deref()
+    }
+
+    /// The column the next text will be printed at.
+    fn next_column(&self) -> usize {
+        if self.applied_indent {
+            self.column
+        } else {
+            self.current_indent
+        }
+    }
+
     fn newline(&mut self) {
         if self.flatten {
             self.column += 1;
         } else {
             self.applied_indent = false;
+            self.line += 1;
+            self.line_overflowed = false;
         }
-        if self.column > self.max_width {
-            self.tainted = true;
-        }
+        self.check_overflow();
     }
 
     fn indent(&mut self, by: isize) {
         self.current_indent = (self.current_indent as isize + by) as usize;
     }
 
-    fn push(&mut self, length: usize) {
+    /// Advances the column by `width`, the number of terminal columns taken up
+    /// by the pushed text.
+    fn push(&mut self, width: usize) {
         if !self.applied_indent {
             self.column = self.current_indent;
             self.applied_indent = true;
+            if self.column + width > self.max_width {
+                self.line_overflowed = true;
+                self.unavoidable_overflows.push(UnavoidableOverflow {
+                    line: self.line,
+                    max_width: self.max_width,
+                });
+            }
         }
-        self.column += length;
-        if self.column > self.max_width {
+        self.column += width;
+        self.check_overflow();
+    }
+
+    fn check_overflow(&mut self) {
+        if self.column > self.max_width
+            && !(self.line_overflowed && self.allow_unavoidable_overflow)
+        {
             self.tainted = true;
         }
     }
 
+    /// Advances past `text`, which is printed as is. Only its first line can
+    /// overflow, since the layout of the rest is not up to the formatter.
+    ///
+    /// A flattened try body cannot hold `text` if it spans lines, so it is
+    /// backtracked out of as if it overflowed.
+    fn push_verbatim(&mut self, text: &str) {
+        if self.flatten && self.trying && text.contains('\n') {
+            self.tainted = true;
+        }
+        let mut lines = text.split('\n');
+        self.push(lines.next().unwrap_or_default().width());
+        for line in lines {
+            self.column = line.width();
+            self.applied_indent = true;
+            self.line += 1;
+            self.line_overflowed = false;
+        }
+    }
+
     fn set_flattened(&mut self) {
         self.flatten = true;
     }
+
+    /// The overflows no layout could have avoided, in order.
+    pub fn unavoidable_overflows(&self) -> &[UnavoidableOverflow] {
+        &self.unavoidable_overflows
+    }
+
+    /// The state resolving the document at `idx` from this context depends
+    /// on.
+    fn memo_key(&self, idx: DocumentIdx) -> MemoKey {
+        MemoKey {
+            idx,
+            max_width: self.max_width,
+            column: self.column,
+            current_indent: self.current_indent,
+            applied_indent: self.applied_indent,
+            flatten: self.flatten,
+            trying: self.trying,
+            tainted: self.tainted,
+            line_overflowed: self.line_overflowed,
+        }
+    }
+
+    /// Advances past a document as recorded in `entry`, as if resolving it
+    /// again from a context with the same [`MemoKey`].
+    fn replay(&mut self, entry: &MemoEntry) {
+        self.column = entry.column;
+        self.applied_indent = entry.applied_indent;
+        self.tainted = entry.tainted;
+        self.line_overflowed = entry.line_overflowed;
+        self.backtracks += entry.backtracks;
+        let line = self.line;
+        self.unavoidable_overflows.extend(
+            entry.unavoidable_overflows.iter().map(|overflow| {
+                UnavoidableOverflow {
+                    line: line + overflow.line,
+                    ..*overflow
+                }
+            }),
+        );
+        self.line += entry.lines;
+    }
+
+    /// How many times resolution fell back to a catch branch because the try
+    /// branch overflowed.
+    pub fn backtracks(&self) -> usize {
+        self.backtracks
+    }
+}
+
+/// The state of a [`PrintingContext`] that resolving the document `idx`
+/// depends on. Indentation, flattening, and the maximum width are restored
+/// once the document is resolved, so only the rest can differ afterwards.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct MemoKey {
+    idx: DocumentIdx,
+    max_width: usize,
+    column: usize,
+    current_indent: usize,
+    applied_indent: bool,
+    flatten: bool,
+    trying: bool,
+    tainted: bool,
+    line_overflowed: bool,
+}
+
+/// The outcome of resolving a document from a [`MemoKey`]: the resolved
+/// document and how the context changed.
+struct MemoEntry {
+    result: DocumentIdx,
+    column: usize,
+    applied_indent: bool,
+    tainted: bool,
+    line_overflowed: bool,
+    /// How many lines the document spans past its first.
+    lines: usize,
+    backtracks: usize,
+    /// With lines relative to the first line of the document.
+    unavoidable_overflows: Vec<UnavoidableOverflow>,
+}
+
+/// A pending step of [`resolve_try_catch`], which keeps its own stack so that
+/// deeply nested documents cannot overflow the call stack. Each `Resolve`
+/// step eventually pushes exactly one resolved document onto the result stack.
+enum ResolveStep {
+    /// Resolve the document at this index in the current context.
+    Resolve(DocumentIdx),
+    /// Wrap the last result in a nest, undoing its indentation.
+    FinishNest(isize),
+    /// Wrap the last result in a flatten, restoring whether the context was
+    /// flattened before it.
+    FinishFlatten(bool),
+    /// Restore the maximum width from before a width override, leaving the
+    /// last result as it is.
+    FinishWithWidth(usize),
+    /// Wrap the last result in the source span it was built from.
+    FinishSpanned(Range<usize>),
+    /// Combine the last this many results into a list.
+    FinishList(usize),
+    /// Keep the last result if its try body fit, otherwise resolve the catch
+    /// body from the context as it was before the try.
+    FinishTry {
+        idx: DocumentIdx,
+        before: Box<PrintingContext>,
+        catch_body_idx: DocumentIdx,
+    },
+    /// Remember how the document resolved into the last result changed the
+    /// context since it was as in `key`, with `line`, `backtracks`, and
+    /// `overflows` unavoidable overflows at the time.
+    Memoize {
+        key: MemoKey,
+        line: usize,
+        backtracks: usize,
+        overflows: usize,
+    },
 }
 
-// TODO: maybe merge top function into this
 /// Invariant: A try will never be expanded after a catch.
+///
+/// Try bodies and catch bodies often share documents, so the outcome of
+/// resolving each try/catch from a given context is remembered and replayed
+/// when it is reached from the same context again, unless recording choices.
 pub fn resolve_try_catch(
     store: &mut InternedDocumentStore,
     idx: DocumentIdx,
     context: &mut PrintingContext,
 ) -> DocumentIdx {
-    match store.get(idx).clone() {
-        Document::Newline => {
-            context.newline();
-            idx
-        }
-        Document::Text(text) => {
-            context.push(text.len());
-            idx
-        }
-        Document::Nest(body_idx, by) => {
-            context.indent(by);
-            let new_body_idx = resolve_try_catch(store, body_idx, context);
-            context.indent(-by);
-            store.add(Document::Nest(new_body_idx, by))
-        }
-        Document::Flatten(body_idx) => {
-            let mut flattened_context = context.clone();
-            flattened_context.set_flattened();
-            let new_body_idx =
-                resolve_try_catch(store, body_idx, &mut flattened_context);
-            flattened_context.flatten = context.flatten;
-            *context = flattened_context;
-            store.add(Document::Flatten(new_body_idx))
-        }
-        Document::List(children) => {
-            let new_children = children
-                .into_iter()
-                .map(|child_idx| resolve_try_catch(store, child_idx, context))
-                .collect();
-            store.add(Document::List(new_children))
-        }
-        Document::TryCatch(try_body_idx, catch_body_idx) => {
-            let mut try_context = context.clone();
-            try_context.trying = true;
-
-            //println!("\ntrying from {:?}", try_context);
-            //let mut buffer = String::new();
-            //let mut f = inform::fmt::IndentWriter::new(&mut buffer, 4);
-            //crate::document::debug_print(store, &mut f, try_body_idx)
-            //    .expect("a");
-            //println!("{}", buffer);
-
-            let new_try_body_idx =
-                resolve_try_catch(store, try_body_idx, &mut try_context);
-            if try_context.tainted && !context.trying {
-                let mut catch_context = context.clone();
-                catch_context.tainted = false;
-
-                //println!(
-                //    "\nfailed to flatten, doing nest from {:?}",
-                //    catch_context
-                //);
-                //let mut buffer = String::new();
-                //let mut f = inform::fmt::IndentWriter::new(&mut buffer, 4);
-                //crate::document::debug_print(store, &mut f, catch_body_idx)
-                //    .expect("a");
-                //println!("{}", buffer);
-
-                let new_catch_body_idx = resolve_try_catch(
-                    store,
-                    catch_body_idx,
-                    &mut catch_context,
-                );
-                *context = catch_context;
-                //println!("\nnested (now tainted = {})", context.tainted);
-                new_catch_body_idx
-            } else {
-                try_context.trying = context.trying;
-                *context = try_context;
-                //println!("\nflattened (now tainted = {})", context.tainted);
-                new_try_body_idx
+    let mut steps = vec![ResolveStep::Resolve(idx)];
+    let mut results = vec![];
+    let mut memo = HashMap::<MemoKey, MemoEntry>::new();
+    while let Some(step) = steps.pop() {
+        match step {
+            ResolveStep::Resolve(idx) => match store.get(idx).clone() {
+                Document::Newline => {
+                    context.newline();
+                    results.push(idx);
+                }
+                Document::Text(text) => {
+                    context.push(text.width());
+                    results.push(idx);
+                }
+                Document::Verbatim(text) => {
+                    context.push_verbatim(&text);
+                    results.push(idx);
+                }
+                Document::Nest(body_idx, by) => {
+                    context.indent(by);
+                    steps.push(ResolveStep::FinishNest(by));
+                    steps.push(ResolveStep::Resolve(body_idx));
+                }
+                Document::Flatten(body_idx) => {
+                    steps.push(ResolveStep::FinishFlatten(context.flatten));
+                    context.set_flattened();
+                    steps.push(ResolveStep::Resolve(body_idx));
+                }
+                Document::List(children) => {
+                    steps.push(ResolveStep::FinishList(children.len()));
+                    steps.extend(
+                        children.into_iter().rev().map(ResolveStep::Resolve),
+                    );
+                }
+                Document::WithWidth(width, body_idx) => {
+                    steps.push(ResolveStep::FinishWithWidth(context.max_width));
+                    context.max_width = width;
+                    steps.push(ResolveStep::Resolve(body_idx));
+                }
+                Document::Spanned(span, body_idx) => {
+                    if context.choices.is_some() {
+                        context.spans.push(span.clone());
+                    }
+                    steps.push(ResolveStep::FinishSpanned(span));
+                    steps.push(ResolveStep::Resolve(body_idx));
+                }
+                Document::Fill(children) => {
+                    // each item after the first is tried after a space on the
+                    // current line, falling back to a new line
+                    let space = store.add(Document::Text(" ".into()));
+                    let newline = store.add(Document::Newline);
+                    let mut list = vec![];
+                    for (i, child) in children.into_iter().enumerate() {
+                        list.push(if i == 0 {
+                            child
+                        } else {
+                            let same_line =
+                                store.add(Document::List(vec![space, child]));
+                            let new_line =
+                                store.add(Document::List(vec![newline, child]));
+                            store.add(Document::TryCatch(same_line, new_line))
+                        });
+                    }
+                    steps.push(ResolveStep::Resolve(
+                        store.add(Document::List(list)),
+                    ));
+                }
+                Document::TryCatch(try_body_idx, catch_body_idx) => {
+                    let key = context.memo_key(idx);
+                    if context.choices.is_none() {
+                        if let Some(entry) = memo.get(&key) {
+                            context.replay(entry);
+                            results.push(entry.result);
+                            continue;
+                        }
+                        steps.push(ResolveStep::Memoize {
+                            key,
+                            line: context.line,
+                            backtracks: context.backtracks,
+                            overflows: context.unavoidable_overflows.len(),
+                        });
+                    }
+                    steps.push(ResolveStep::FinishTry {
+                        idx,
+                        before: Box::new(context.clone()),
+                        catch_body_idx,
+                    });
+                    context.trying = true;
+
+                    tracing::trace!(
+                        ?try_body_idx,
+                        column = context.column,
+                        "trying"
+                    );
+
+                    steps.push(ResolveStep::Resolve(try_body_idx));
+                }
+            },
+            ResolveStep::FinishNest(by) => {
+                context.indent(-by);
+                let body_idx = results.pop().expect("nest body was resolved");
+                results.push(store.add(Document::Nest(body_idx, by)));
+            }
+            ResolveStep::FinishFlatten(was_flattened) => {
+                context.flatten = was_flattened;
+                let body_idx =
+                    results.pop().expect("flatten body was resolved");
+                results.push(store.add(Document::Flatten(body_idx)));
+            }
+            ResolveStep::FinishWithWidth(max_width) => {
+                context.max_width = max_width;
+            }
+            ResolveStep::FinishSpanned(span) => {
+                context.spans.pop();
+                let body_idx =
+                    results.pop().expect("spanned body was resolved");
+                results.push(store.add(Document::Spanned(span, body_idx)));
+            }
+            ResolveStep::FinishList(length) => {
+                let children = results.split_off(results.len() - length);
+                results.push(store.add(Document::List(children)));
+            }
+            ResolveStep::FinishTry {
+                idx,
+                before,
+                catch_body_idx,
+            } => {
+                let mut choice = Choice {
+                    idx,
+                    span: context.spans.last().cloned(),
+                    start_column: before.next_column(),
+                    end_column: context.column,
+                    max_width: context.max_width,
+                    kept_try: true,
+                };
+                if context.tainted && !before.trying {
+                    results.pop().expect("try body was resolved");
+                    *context = *before;
+                    context.tainted = false;
+                    choice.kept_try = false;
+
+                    context.backtracks += 1;
+                    tracing::trace!(
+                        ?catch_body_idx,
+                        column = context.column,
+                        "try overflowed, backtracking to catch"
+                    );
+
+                    steps.push(ResolveStep::Resolve(catch_body_idx));
+                } else {
+                    context.trying = before.trying;
+                }
+                if let Some(choices) = &mut context.choices {
+                    choices.push(choice);
+                }
+            }
+            ResolveStep::Memoize {
+                key,
+                line,
+                backtracks,
+                overflows,
+            } => {
+                let entry = MemoEntry {
+                    result: *results.last().expect("try/catch was resolved"),
+                    column: context.column,
+                    applied_indent: context.applied_indent,
+                    tainted: context.tainted,
+                    line_overflowed: context.line_overflowed,
+                    lines: context.line - line,
+                    backtracks: context.backtracks - backtracks,
+                    unavoidable_overflows: context.unavoidable_overflows
+                        [overflows..]
+                        .iter()
+                        .map(|overflow| UnavoidableOverflow {
+                            line: overflow.line - line,
+                            ..*overflow
+                        })
+                        .collect(),
+                };
+                memo.insert(key, entry);
             }
         }
     }
+    results.pop().expect("root document was resolved")
 }
@@ -0,0 +1,166 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Statistics on how formatting changed each file, for tuning the
+//! configuration across a codebase.
+
+use std::{
+    io::{self, Write},
+    mem,
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde_json::{Value, json};
+use unicode_width::UnicodeWidthStr;
+
+/// How formatting changed one file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FormatStats {
+    /// The number of lines of the output that are not lines of the input.
+    pub changed_lines: usize,
+    /// How many groups had to be broken across lines because they did not
+    /// fit, if known.
+    pub broken_groups: Option<usize>,
+    /// The width of the widest line of the output.
+    pub max_line_width: usize,
+}
+
+impl FormatStats {
+    /// Compares `source` to `output`, the exact bytes it was formatted to.
+    pub fn new(
+        source: &str,
+        output: &str,
+        broken_groups: Option<usize>,
+    ) -> Self {
+        let source_lines = source.lines().collect::<Vec<_>>();
+        let output_lines = output.lines().collect::<Vec<_>>();
+        Self {
+            changed_lines: output_lines.len()
+                - longest_common_subsequence(&source_lines, &output_lines),
+            broken_groups,
+            max_line_width: output_lines
+                .iter()
+                .map(|line| line.width())
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    /// A machine-readable record of these statistics for the file at `path`.
+    pub fn json_record(&self, path: &Utf8Path) -> Value {
+        json!({
+            "file": path,
+            "changed_lines": self.changed_lines,
+            "broken_groups": self.broken_groups,
+            "max_line_width": self.max_line_width,
+        })
+    }
+}
+
+/// The length of the longest common subsequence of `a` and `b`.
+fn longest_common_subsequence(a: &[&str], b: &[&str]) -> usize {
+    // lines around the changes are usually untouched, and skipping them keeps
+    // the quadratic part small
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    let mut previous = vec![0; b.len() + 1];
+    let mut current = vec![0; b.len() + 1];
+    for a_line in a {
+        for (j, b_line) in b.iter().enumerate() {
+            current[j + 1] = if a_line == b_line {
+                previous[j] + 1
+            } else {
+                current[j].max(previous[j + 1])
+            };
+        }
+        mem::swap(&mut previous, &mut current);
+    }
+    prefix + previous[b.len()] + suffix
+}
+
+/// Writes `rows` to `output` as a table with one row per file, followed by
+/// the totals across all of them.
+pub fn emit_table(
+    rows: &[(Utf8PathBuf, FormatStats)],
+    output: &mut impl Write,
+) -> io::Result<()> {
+    const FILE_HEADER: &str = "file";
+    let file_width = rows
+        .iter()
+        .map(|(path, _)| path.as_str().width())
+        .chain([FILE_HEADER.len()])
+        .max()
+        .unwrap_or(0);
+    let broken_groups = |stats: &FormatStats| {
+        stats
+            .broken_groups
+            .map_or_else(|| "-".to_string(), |count| count.to_string())
+    };
+
+    writeln!(
+        output,
+        "{FILE_HEADER:file_width$}  {:>7}  {:>7}  {:>7}",
+        "changed", "broken", "widest"
+    )?;
+    for (path, stats) in rows {
+        writeln!(
+            output,
+            "{:file_width$}  {:>7}  {:>7}  {:>7}",
+            path.as_str(),
+            stats.changed_lines,
+            broken_groups(stats),
+            stats.max_line_width
+        )?;
+    }
+
+    let total = FormatStats {
+        changed_lines: rows.iter().map(|(_, stats)| stats.changed_lines).sum(),
+        broken_groups: rows
+            .iter()
+            .map(|(_, stats)| stats.broken_groups)
+            .sum::<Option<usize>>(),
+        max_line_width: rows
+            .iter()
+            .map(|(_, stats)| stats.max_line_width)
+            .max()
+            .unwrap_or(0),
+    };
+    writeln!(
+        output,
+        "{:file_width$}  {:>7}  {:>7}  {:>7}",
+        format!("total ({} files)", rows.len()),
+        total.changed_lines,
+        broken_groups(&total),
+        total.max_line_width
+    )
+}
+
+/// Writes `rows` to `output` as one line-delimited JSON record per file (see
+/// [`FormatStats::json_record`]).
+pub fn emit_json(
+    rows: &[(Utf8PathBuf, FormatStats)],
+    output: &mut impl Write,
+) -> io::Result<()> {
+    for (path, stats) in rows {
+        writeln!(output, "{}", stats.json_record(path))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Formatting a whole [Swim](https://gitlab.com/spade-lang/swim) project as
+//! laid out by its `swim.toml` manifest.
+
+use std::fs;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+use snafu::ResultExt;
+
+use crate::error::{ConfigSnafu, FormatError, IoSnafu};
+
+/// The name of the manifest at the root of every Swim project.
+pub const MANIFEST_FILE_NAME: &str = "swim.toml";
+
+/// The directory, relative to a project root, that Swim compiles sources
+/// from.
+const SOURCE_DIRECTORY: &str = "src";
+
+/// The parts of a `swim.toml` that determine which files belong to the
+/// project. Everything else in the manifest is ignored.
+#[derive(Deserialize, Debug)]
+struct Manifest {
+    #[serde(default)]
+    libraries: toml::Table,
+}
+
+/// A Swim project and the Spade sources it is made of.
+#[derive(Debug)]
+pub struct Project {
+    /// The directory containing the manifest.
+    pub root: Utf8PathBuf,
+    /// Every `.spade` file in the source directories of the project and of
+    /// the libraries it declares by a path inside the project, in sorted
+    /// order.
+    pub files: Vec<Utf8PathBuf>,
+}
+
+impl Project {
+    /// Reads the manifest at `manifest_path` and collects the sources of the
+    /// project it describes.
+    ///
+    /// Libraries declared with a `path` are formatted along with the project
+    /// only if they live inside it; libraries elsewhere, including those
+    /// fetched with `git`, belong to someone else.
+    pub fn from_manifest(
+        manifest_path: &Utf8Path,
+    ) -> Result<Self, FormatError> {
+        let contents = fs::read_to_string(manifest_path).context(IoSnafu {
+            path: manifest_path,
+        })?;
+        let manifest =
+            toml::from_str::<Manifest>(&contents).context(ConfigSnafu {
+                path: manifest_path,
+            })?;
+
+        let root = manifest_path
+            .parent()
+            .filter(|parent| !parent.as_str().is_empty())
+            .unwrap_or(Utf8Path::new("."))
+            .to_path_buf();
+        let canonical_root =
+            root.canonicalize_utf8().context(IoSnafu { path: &root })?;
+
+        let mut source_directories = vec![root.join(SOURCE_DIRECTORY)];
+        for library in manifest.libraries.values() {
+            let Some(path) = library.get("path").and_then(|path| path.as_str())
+            else {
+                continue;
+            };
+            let library_root = root.join(path);
+            let is_inside =
+                library_root.canonicalize_utf8().is_ok_and(|library_root| {
+                    library_root.starts_with(&canonical_root)
+                });
+            if is_inside {
+                source_directories.push(library_root.join(SOURCE_DIRECTORY));
+            }
+        }
+
+        let mut files = vec![];
+        for directory in source_directories {
+            if directory.is_dir() {
+                collect_spade_files(&directory, &mut files)?;
+            }
+        }
+        files.sort();
+        files.dedup();
+
+        Ok(Self { root, files })
+    }
+}
+
+/// Finds the nearest [`MANIFEST_FILE_NAME`] in `directory` or one of its
+/// ancestors.
+pub fn find_manifest(directory: &Utf8Path) -> Option<Utf8PathBuf> {
+    let directory = directory
+        .canonicalize_utf8()
+        .unwrap_or_else(|_| directory.to_path_buf());
+    directory
+        .ancestors()
+        .map(|ancestor| ancestor.join(MANIFEST_FILE_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Adds every `.spade` file under `directory` to `files`.
+fn collect_spade_files(
+    directory: &Utf8Path,
+    files: &mut Vec<Utf8PathBuf>,
+) -> Result<(), FormatError> {
+    let entries = directory
+        .read_dir_utf8()
+        .context(IoSnafu { path: directory })?;
+    for entry in entries {
+        let entry = entry.context(IoSnafu { path: directory })?;
+        let path = entry.path();
+        if path.is_dir() {
+            collect_spade_files(path, files)?;
+        } else if path.extension() == Some("spade") {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(())
+}
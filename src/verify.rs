@@ -0,0 +1,55 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Checking that formatting preserved the meaning of the code, by comparing
+//! the syntax trees of the input and the output.
+
+use std::iter;
+
+use spade_ast as ast;
+use spade_codespan_reporting::files::{Files, SimpleFile};
+
+use crate::{
+    FormatError, document_builder::span_of_item, error::AstMismatchSnafu, parse,
+};
+
+/// Whether `a` and `b` are the same syntax tree, ignoring where in the source
+/// each node is. Spans are ignored because [`spade_common::location_info::Loc`]
+/// compares only the node it wraps, so this is plain equality.
+pub fn same_ast(a: &ast::ModuleBody, b: &ast::ModuleBody) -> bool {
+    a == b
+}
+
+/// Parses `source` and its formatted form `formatted` and checks that they
+/// have the same syntax tree, reporting the first top-level item of `source`
+/// that differs otherwise.
+pub fn verify(source: &str, formatted: &str) -> Result<(), FormatError> {
+    let before = parse(source)?;
+    let after = parse(formatted)?;
+    if same_ast(&before, &after) {
+        return Ok(());
+    }
+
+    let file = SimpleFile::new(String::new(), source);
+    let line = before
+        .members
+        .iter()
+        .zip(after.members.iter().map(Some).chain(iter::repeat(None)))
+        .find(|(before_item, after_item)| Some(*before_item) != *after_item)
+        .map(|(item, _)| {
+            file.line_index((), span_of_item(item).start().to_usize())
+                .map_or(1, |index| index + 1)
+        })
+        .unwrap_or_else(|| file.line_index((), source.len()).unwrap_or(0) + 1);
+    AstMismatchSnafu { line }.fail()
+}
@@ -0,0 +1,77 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+
+use crate::config::Config;
+
+/// Information about how this build of `spadefmt` was made, captured by the
+/// build script.
+#[derive(Debug, Clone, Copy)]
+pub struct VersionInfo {
+    /// The version of the `spadefmt` crate.
+    pub version: &'static str,
+    /// The git revision of the Spade compiler crates, including the parser.
+    pub spade_revision: &'static str,
+    /// The version of the Spade parser crate at [`Self::spade_revision`].
+    pub spade_parser_version: &'static str,
+    /// The enabled cargo features, comma-separated.
+    pub features: &'static str,
+}
+
+pub const VERSION_INFO: VersionInfo = VersionInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    spade_revision: env!("SPADEFMT_SPADE_REVISION"),
+    spade_parser_version: env!("SPADEFMT_SPADE_PARSER_VERSION"),
+    features: env!("SPADEFMT_FEATURES"),
+};
+
+impl VersionInfo {
+    /// The enabled cargo features.
+    pub fn features(&self) -> impl Iterator<Item = &'static str> {
+        self.features
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+    }
+
+    /// The Spade parser this build formats with, e.g., for bug reports, as
+    /// formatting depends on how it parses.
+    pub fn parser(&self) -> String {
+        format!(
+            "spade-parser {} (revision {})",
+            self.spade_parser_version, self.spade_revision
+        )
+    }
+
+    /// Displays the build information along with the default configuration.
+    pub fn verbose(&self) -> VerboseVersionInfo {
+        VerboseVersionInfo(*self)
+    }
+}
+
+/// See [`VersionInfo::verbose`].
+pub struct VerboseVersionInfo(VersionInfo);
+
+impl fmt::Display for VerboseVersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let info = &self.0;
+        writeln!(f, "spade revision: {}", info.spade_revision)?;
+        let features = info.features().collect::<Vec<_>>();
+        if features.is_empty() {
+            writeln!(f, "features: (none)")?;
+        } else {
+            writeln!(f, "features: {}", features.join(", "))?;
+        }
+        write!(f, "default config: {:#?}", Config::default())
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Finds and loads the configuration for files in a temporary directory tree.
+
+use std::{env, fs, process};
+
+use camino::Utf8PathBuf;
+use spadefmt::config::{
+    Config,
+    discover::{CONFIG_FILE_NAME, discover, find_config_file},
+};
+
+/// A fresh directory for the test `name`, removed when dropped.
+struct TempTree(Utf8PathBuf);
+
+impl TempTree {
+    fn new(name: &str) -> Self {
+        let root = Utf8PathBuf::try_from(env::temp_dir())
+            .expect("temporary directory is UTF-8")
+            .join(format!("spadefmt-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).expect("failed to create temporary tree");
+        Self(root.canonicalize_utf8().expect("tree exists"))
+    }
+
+    /// Writes `contents` to `relative` under the tree, creating directories
+    /// as needed, and returns its path.
+    fn write(&self, relative: &str, contents: &str) -> Utf8PathBuf {
+        let path = self.0.join(relative);
+        fs::create_dir_all(path.parent().expect("files have a parent"))
+            .expect("failed to create directories");
+        fs::write(&path, contents).expect("failed to write file");
+        path
+    }
+
+    fn path(&self, relative: &str) -> Utf8PathBuf {
+        self.0.join(relative)
+    }
+}
+
+impl Drop for TempTree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn nearest_file_wins() {
+    let tree = TempTree::new("nearest");
+    tree.write(CONFIG_FILE_NAME, "max_width = 80\n");
+    let nearest =
+        tree.write(&format!("sub/{CONFIG_FILE_NAME}"), "max_width = 60\n");
+    let file = tree.write("sub/deep/file.spade", "");
+
+    assert_eq!(find_config_file(&tree.path("sub/deep")), Some(nearest));
+    let config = discover(&file, None).expect("config loads");
+    assert_eq!(config.max_width.inner, 60);
+}
+
+#[test]
+fn config_path_overrides_discovery() {
+    let tree = TempTree::new("config-path");
+    tree.write(CONFIG_FILE_NAME, "max_width = 80\n");
+    let explicit = tree.write("elsewhere/custom.toml", "max_width = 120\n");
+    let file = tree.write("file.spade", "");
+
+    let config =
+        discover(&file, Some(explicit.as_path())).expect("config loads");
+    assert_eq!(config.max_width.inner, 120);
+}
+
+#[test]
+fn defaults_apply_without_a_file() {
+    let tree = TempTree::new("defaults");
+    let file = tree.write("file.spade", "");
+
+    assert_eq!(find_config_file(&tree.path("")), None);
+    let config = discover(&file, None).expect("config loads");
+    assert_eq!(format!("{config:?}"), format!("{:?}", Config::default()));
+}
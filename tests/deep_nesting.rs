@@ -0,0 +1,82 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Formatting must not overflow the stack on deeply nested code, such as
+//! large generated sources, nor resolving and printing on the deeply nested
+//! documents built from it.
+
+use spadefmt::{
+    config::Config,
+    document::{self, Document, InternedDocumentStore},
+    format_source,
+    indent_writer::IndentWriter,
+    resolve_try_catch::{PrintingContext, resolve_try_catch},
+};
+
+const DEPTH: usize = 10_000;
+
+#[test]
+fn deeply_nested_document() {
+    let mut store = InternedDocumentStore::default();
+    let open = store.add(Document::Text("(".into()));
+    let close = store.add(Document::Text(")".into()));
+    let newline = store.add(Document::Newline);
+
+    // `(...(x)...)`, where each level may instead break its contents onto an
+    // indented line
+    let mut idx = store.add(Document::Text("x".into()));
+    for _ in 0..DEPTH {
+        let nested = store.add(Document::Nest(idx, 0));
+        let flat = store.add(Document::List(vec![open, nested, close]));
+        let broken_body = store.add(Document::List(vec![newline, idx]));
+        let broken_nest = store.add(Document::Nest(broken_body, 1));
+        let broken =
+            store.add(Document::List(vec![open, broken_nest, newline, close]));
+        idx = store.add(Document::TryCatch(flat, broken));
+    }
+
+    let resolved_idx = resolve_try_catch(
+        &mut store,
+        idx,
+        &mut PrintingContext::new(usize::MAX),
+    );
+    assert_eq!(document::check_resolved(&store, resolved_idx), Ok(()));
+
+    let mut buffer = String::new();
+    document::print_resolved(
+        &store,
+        &mut IndentWriter::new(&mut buffer),
+        resolved_idx,
+        false,
+        &mut false,
+    )
+    .expect("printing to a string cannot fail");
+    assert_eq!(
+        buffer,
+        format!("{}x{}", "(".repeat(DEPTH), ")".repeat(DEPTH))
+    );
+}
+
+#[test]
+fn deeply_nested_expression() {
+    let nested = format!("{}a{}", "(".repeat(DEPTH), ")".repeat(DEPTH));
+    let source = format!("fn f() -> int<8> {{ {nested} }}");
+
+    let config = Config::default();
+    let formatted = format_source(&source, &config).expect("formats");
+    assert!(formatted.lines().any(|line| line.trim() == nested));
+    assert_eq!(
+        format_source(&formatted, &config).expect("formats again"),
+        formatted
+    );
+}
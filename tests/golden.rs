@@ -0,0 +1,89 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Formats every `.spade` file in `tests/corpus` and compares the output to
+//! the `.expected` snapshot next to it. Run with `UPDATE_SNAPSHOTS=1` to
+//! rewrite the snapshots from the current output instead.
+
+use std::{env, fs};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use spadefmt::{config::Config, format_source};
+
+/// Each input in the corpus along with the snapshot of its formatted output,
+/// sorted by input.
+fn cases() -> Vec<(Utf8PathBuf, Utf8PathBuf)> {
+    let directory =
+        Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut inputs = fs::read_dir(&directory)
+        .unwrap_or_else(|_| panic!("failed to read {directory}"))
+        .map(|entry| {
+            Utf8PathBuf::try_from(entry.expect("failed to read entry").path())
+                .expect("test paths are UTF-8")
+        })
+        .filter(|path| path.extension() == Some("spade"))
+        .collect::<Vec<_>>();
+    inputs.sort();
+    inputs
+        .into_iter()
+        .map(|input| {
+            let snapshot = input.with_extension("expected");
+            (input, snapshot)
+        })
+        .collect()
+}
+
+fn format(path: &Utf8Path, source: &str) -> String {
+    match format_source(source, &Config::default()) {
+        Ok(formatted) => format!("{formatted}\n"),
+        Err(error) => panic!("{path}: {error}"),
+    }
+}
+
+#[test]
+fn golden() {
+    let update = env::var("UPDATE_SNAPSHOTS").is_ok_and(|value| value == "1");
+
+    let mut failures = vec![];
+    for (input, snapshot) in cases() {
+        let source = fs::read_to_string(&input).expect("failed to read input");
+        let formatted = format(&input, &source);
+
+        if update {
+            fs::write(&snapshot, &formatted).expect("failed to write snapshot");
+            continue;
+        }
+
+        match fs::read_to_string(&snapshot) {
+            Ok(expected) if expected == formatted => {}
+            Ok(expected) => failures.push(format!(
+                "{input}: output differs from {snapshot}\n--- expected\n\
+                 {expected}--- actual\n{formatted}"
+            )),
+            Err(_) => failures.push(format!(
+                "{input}: no snapshot at {snapshot}; run with \
+                 UPDATE_SNAPSHOTS=1 to create it"
+            )),
+        }
+
+        let reformatted = format(&input, &formatted);
+        if reformatted != formatted {
+            failures.push(format!(
+                "{input}: formatting is not idempotent\n--- once\n\
+                 {formatted}--- twice\n{reformatted}"
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
@@ -0,0 +1,66 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! The resolver must measure lines under a nest exactly as the printer writes
+//! them, indentation included, or lines it thinks fit come out overlong.
+
+use spadefmt::{
+    document::{self, Document, DocumentIdx, InternedDocumentStore},
+    indent_writer::IndentWriter,
+    resolve_try_catch::{PrintingContext, resolve_try_catch},
+};
+
+const MAX_WIDTH: usize = 20;
+const INDENT: isize = 4;
+
+/// A line break nested by [`INDENT`] followed by a choice between `word` and
+/// `fallback`, resolved and printed.
+fn resolve_nested_choice(word: &str, fallback: &str) -> String {
+    let mut store = InternedDocumentStore::default();
+    let newline = store.add(Document::Newline);
+    let word = store.add(Document::Text(word.into()));
+    let fallback = store.add(Document::Text(fallback.into()));
+    let choice = store.add(Document::TryCatch(word, fallback));
+    let body = store.add(Document::List(vec![newline, choice]));
+    let idx = store.add(Document::Nest(body, INDENT));
+    print(&mut store, idx)
+}
+
+fn print(store: &mut InternedDocumentStore, idx: DocumentIdx) -> String {
+    let resolved_idx =
+        resolve_try_catch(store, idx, &mut PrintingContext::new(MAX_WIDTH));
+    let mut buffer = String::new();
+    document::print_resolved(
+        store,
+        &mut IndentWriter::new(&mut buffer),
+        resolved_idx,
+        false,
+        &mut false,
+    )
+    .expect("printing to a string cannot fail");
+    buffer
+}
+
+#[test]
+fn nested_line_that_fits_exactly_is_kept() {
+    let word = "x".repeat(MAX_WIDTH - INDENT as usize);
+    let printed = resolve_nested_choice(&word, "y");
+    assert_eq!(printed, format!("\n    {word}"));
+    assert!(printed.lines().all(|line| line.len() <= MAX_WIDTH));
+}
+
+#[test]
+fn nested_line_overflowing_by_its_indentation_falls_back() {
+    let word = "x".repeat(MAX_WIDTH - INDENT as usize + 1);
+    assert_eq!(resolve_nested_choice(&word, "y"), "\n    y");
+}
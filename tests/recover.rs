@@ -0,0 +1,35 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Recovers from a file with one item that does not parse, checking that the
+//! items around it are formatted and it is kept as written.
+
+use spadefmt::{config::Config, recover::format_recovering};
+
+#[test]
+fn one_bad_item_is_kept_as_written() {
+    let source = "fn first(a: int<8>) -> int<8> {   a }\n\n\
+                  fn broken(a: int<8> -> int<8> {\n    a\n}\n\n\
+                  fn last(b: int<8>) -> int<8> { b   }\n";
+
+    let (formatted, warnings) =
+        format_recovering(source, &Config::default()).expect("recovers");
+
+    assert_eq!(
+        formatted,
+        "fn first(a: int<8>) -> int<8> {\n    a\n}\n\n\
+         fn broken(a: int<8> -> int<8> {\n    a\n}\n\n\
+         fn last(b: int<8>) -> int<8> {\n    b\n}"
+    );
+    assert_eq!(warnings.len(), 1);
+}
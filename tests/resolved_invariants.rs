@@ -0,0 +1,98 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Resolves randomly generated documents and checks that the result upholds
+//! every invariant [`document::check_resolved`] knows of.
+
+use proptest::prelude::*;
+use spadefmt::{
+    document::{self, Document, DocumentIdx, InternedDocumentStore},
+    resolve_try_catch::{PrintingContext, resolve_try_catch},
+};
+
+/// The shape of a document, built the way the document builder builds them:
+/// nests only indent, and flattening is only ever tried, as a group.
+#[derive(Clone, Debug)]
+enum Shape {
+    Newline,
+    Text(String),
+    Verbatim(String),
+    Nest(Box<Shape>, isize),
+    List(Vec<Shape>),
+    Fill(Vec<Shape>),
+    WithWidth(usize, Box<Shape>),
+    /// The body on one line if it fits, and as is otherwise.
+    Group(Box<Shape>),
+}
+
+fn shape() -> impl Strategy<Value = Shape> {
+    let leaf = prop_oneof![
+        Just(Shape::Newline),
+        "[a-z]{1,12}".prop_map(Shape::Text),
+        prop::collection::vec("[a-z ]{0,8}", 1..4)
+            .prop_map(|lines| Shape::Verbatim(lines.join("\n"))),
+    ];
+    leaf.prop_recursive(6, 64, 4, |inner| {
+        prop_oneof![
+            (inner.clone(), 0isize..8)
+                .prop_map(|(body, by)| Shape::Nest(Box::new(body), by)),
+            prop::collection::vec(inner.clone(), 0..4).prop_map(Shape::List),
+            prop::collection::vec(inner.clone(), 0..4).prop_map(Shape::Fill),
+            (4usize..40, inner.clone()).prop_map(|(width, body)| {
+                Shape::WithWidth(width, Box::new(body))
+            }),
+            inner.prop_map(|body| Shape::Group(Box::new(body))),
+        ]
+    })
+}
+
+fn build(store: &mut InternedDocumentStore, shape: &Shape) -> DocumentIdx {
+    let document = match shape {
+        Shape::Newline => Document::Newline,
+        Shape::Text(text) => Document::Text(text.clone()),
+        Shape::Verbatim(text) => Document::Verbatim(text.clone()),
+        Shape::Nest(body, by) => Document::Nest(build(store, body), *by),
+        Shape::List(children) => Document::List(
+            children.iter().map(|child| build(store, child)).collect(),
+        ),
+        Shape::Fill(children) => Document::Fill(
+            children.iter().map(|child| build(store, child)).collect(),
+        ),
+        Shape::WithWidth(width, body) => {
+            Document::WithWidth(*width, build(store, body))
+        }
+        Shape::Group(body) => {
+            let body = build(store, body);
+            let flat = store.add(Document::Flatten(body));
+            Document::TryCatch(flat, body)
+        }
+    };
+    store.add(document)
+}
+
+proptest! {
+    #[test]
+    fn resolved_documents_pass_check(
+        shape in shape(),
+        max_width in 4usize..40,
+    ) {
+        let mut store = InternedDocumentStore::default();
+        let idx = build(&mut store, &shape);
+        let resolved_idx = resolve_try_catch(
+            &mut store,
+            idx,
+            &mut PrintingContext::new(max_width),
+        );
+        prop_assert_eq!(document::check_resolved(&store, resolved_idx), Ok(()));
+    }
+}
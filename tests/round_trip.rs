@@ -0,0 +1,88 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Formats randomly generated Spade units and checks that the output parses to
+//! the same syntax tree and is stable under reformatting.
+
+use proptest::prelude::*;
+use spadefmt::{config::Config, format_source, parse, verify::verify};
+
+const OPERATORS: &[&str] = &[
+    "+", "-", "*", "==", "!=", "<", ">", "<=", ">=", "&&", "||", "&", "|", "^",
+    "<<", ">>",
+];
+
+/// Random expressions over the parameters `a`, `b`, and `c`.
+fn expression() -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        prop::sample::select(vec!["a", "b", "c"]).prop_map(String::from),
+        (0u32..1000).prop_map(|value| value.to_string()),
+    ];
+    leaf.prop_recursive(6, 64, 4, |inner| {
+        prop_oneof![
+            (
+                inner.clone(),
+                prop::sample::select(OPERATORS),
+                inner.clone()
+            )
+                .prop_map(|(left, op, right)| format!("{left} {op} {right}")),
+            inner.clone().prop_map(|inner| format!("({inner})")),
+            inner.clone().prop_map(|inner| format!("!({inner})")),
+            (inner.clone(), inner.clone(), inner.clone()).prop_map(
+                |(condition, then, otherwise)| format!(
+                    "(if {condition} {{ {then} }} else {{ {otherwise} }})"
+                )
+            ),
+            prop::collection::vec(inner, 1..4)
+                .prop_map(|elements| format!("[{}]", elements.join(", "))),
+        ]
+    })
+}
+
+/// A function whose body binds and returns random expressions, with random
+/// layout whitespace between tokens.
+fn unit() -> impl Strategy<Value = String> {
+    (
+        prop::collection::vec(expression(), 0..4),
+        expression(),
+        prop::sample::select(vec![" ", "\n", "\n\n", "    "]),
+    )
+        .prop_map(|(bindings, result, separator)| {
+            let mut source = String::from(
+                "fn f(a: int<8>, b: int<8>, c: int<8>) -> int<8> {",
+            );
+            for (i, binding) in bindings.iter().enumerate() {
+                source.push_str(&format!("{separator}let x{i} = {binding};"));
+            }
+            source.push_str(&format!("{separator}{result}{separator}}}"));
+            source
+        })
+}
+
+proptest! {
+    #[test]
+    fn formatting_preserves_ast(source in unit()) {
+        // the generator does not know every rule of the grammar, e.g., how
+        // comparisons associate, so inputs that do not parse are skipped
+        prop_assume!(parse(&source).is_ok());
+
+        let config = Config::default();
+        let formatted = format_source(&source, &config)
+            .expect("generated source should format");
+        prop_assert!(verify(&source, &formatted).is_ok(), "{formatted}");
+
+        let reformatted = format_source(&formatted, &config)
+            .expect("formatted source should format");
+        prop_assert_eq!(reformatted, formatted);
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright (C) 2024 Ethan Uppal.
+//
+// This file is part of spadefmt.
+//
+// spadefmt is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version. spadefmt is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details. You should have received a copy of the GNU General Public License
+// along with spadefmt. If not, see <https://www.gnu.org/licenses/>.
+
+//! Formats a synthetic one-line file over a thousand columns wide, such as a
+//! generator might produce, which is built in always-break mode.
+
+use spadefmt::{
+    WIDE_INPUT_LINE_LENGTH, config::Config, format_source, is_wide_input,
+};
+use unicode_width::UnicodeWidthStr;
+
+const TERMS: usize = 300;
+
+#[test]
+fn one_line_file_is_broken_and_stable() {
+    let source =
+        format!("fn wide() -> int<8> {{ {} }}", ["a"; TERMS].join(" + "));
+    assert!(source.len() > WIDE_INPUT_LINE_LENGTH);
+    assert!(is_wide_input(&source));
+
+    let config = Config::default();
+    let formatted = format_source(&source, &config).expect("formats");
+    assert!(
+        formatted
+            .lines()
+            .all(|line| line.width() <= config.max_width.inner),
+        "a line is wider than max_width:\n{formatted}"
+    );
+    // the signature, each term on its own line, and the closing brace
+    assert_eq!(formatted.lines().count(), TERMS + 2);
+
+    // the output is no longer wide, so it is formatted as usual the second
+    // time, which has to agree with always-break mode
+    assert!(!is_wide_input(&formatted));
+    assert_eq!(
+        format_source(&formatted, &config).expect("formats again"),
+        formatted
+    );
+}